@@ -0,0 +1,121 @@
+use chrono::{DateTime, Local};
+use std::fmt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// How much attention an event needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+// Something the acquisition layer wants the user to know about
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub severity: Severity,
+    pub time: DateTime<Local>,
+    // device id, or what else the event came from
+    pub source: String,
+    pub message: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}: {}",
+            self.time.format("%H:%M:%S"),
+            self.severity,
+            self.source,
+            self.message
+        )
+    }
+}
+
+// Sends events to the UI. Sending never fails loudly, the UI may already be gone.
+#[derive(Debug, Clone)]
+pub struct Events {
+    source: String,
+    tx: UnboundedSender<Event>,
+}
+
+impl Events {
+    pub fn channel(source: &str) -> (Events, UnboundedReceiver<Event>) {
+        let (tx, rx) = unbounded_channel();
+        (
+            Events {
+                source: source.to_string(),
+                tx,
+            },
+            rx,
+        )
+    }
+
+    // Same channel, reporting as something else
+    pub fn with_source(&self, source: &str) -> Events {
+        Events {
+            source: source.to_string(),
+            tx: self.tx.clone(),
+        }
+    }
+
+    pub fn send(&self, severity: Severity, message: String) {
+        let level = match severity {
+            Severity::Info => log::Level::Info,
+            Severity::Warning => log::Level::Warn,
+            Severity::Error => log::Level::Error,
+        };
+        log::log!(level, "{}: {}", self.source, message);
+
+        let _ = self.tx.send(Event {
+            severity,
+            time: Local::now(),
+            source: self.source.clone(),
+            message,
+        });
+    }
+
+    pub fn info(&self, message: String) {
+        self.send(Severity::Info, message);
+    }
+
+    pub fn warn(&self, message: String) {
+        self.send(Severity::Warning, message);
+    }
+
+    pub fn error(&self, message: String) {
+        self.send(Severity::Error, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_channel() {
+        let (events, mut rx) = Events::channel("app");
+        events
+            .with_source("7B45F72B")
+            .warn("low battery".to_string());
+
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.severity, Severity::Warning);
+        assert_eq!(event.source, "7B45F72B");
+
+        // nobody listening is not an error
+        drop(rx);
+        events.error("lost".to_string());
+    }
+}
@@ -1,166 +1,650 @@
-use super::setting::Setting;
+use super::setting::{Compression, Format, Rotation, RrLayout, Setting, Timing, ECG_RATE};
 use crate::menu::{Meta, Paths};
 use arctic::{H10MeasurementType, HeartRate, PmdData, PmdRead};
+use flate2::write::GzEncoder;
+use std::fmt;
+use std::io::Write;
 use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::{
-    fs::OpenOptions,
+    fs::{self, File, OpenOptions},
     io::{AsyncWriteExt, BufWriter, Error},
 };
 
-enum MeasureType {
+#[derive(Debug, Clone, Copy)]
+pub enum MeasureType {
     Hr,
     Ecg,
     Acc,
 }
 
-impl ToString for MeasureType {
-    fn to_string(&self) -> String {
+impl MeasureType {
+    pub fn index(&self) -> usize {
         match self {
+            MeasureType::Hr => 0,
+            MeasureType::Ecg => 1,
+            MeasureType::Acc => 2,
+        }
+    }
+}
+
+impl fmt::Display for MeasureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             MeasureType::Hr => "time,bpm,rr\n",
             MeasureType::Ecg => "time,val\n",
             MeasureType::Acc => "time,x,y,z\n",
-        }
-        .to_string()
+        })
     }
 }
 
+// A single decoded sample from the PMD data stream
+#[derive(Debug, Clone, Copy)]
+pub enum Sample {
+    Ecg(i32),
+    Acc(i16, i16, i16),
+}
+
 // Create/Truncate all data
-pub async fn init(
-    Setting { hr, ecg, acc, .. }: Setting,
-    metadata: Meta,
-    paths: Paths,
-) -> Result<(), Error> {
+pub async fn init(settings: Setting, metadata: Meta, paths: Paths) -> Result<(), Error> {
+    let Setting {
+        hr,
+        ecg,
+        acc,
+        format,
+        raw,
+        ..
+    } = settings;
+
+    if raw {
+        super::raw::init(&paths.raw)?;
+    }
+
+    // everything goes into one database, so there are no per-stream files
+    if format == Format::Sqlite {
+        super::sqlite::open(&paths.db).map_err(Error::other)?;
+        return Ok(());
+    }
+
+    if format == Format::Parquet {
+        for (selected, path) in [(hr, &paths.hr), (ecg, &paths.ecg), (acc, &paths.acc)] {
+            if selected {
+                check_writable(path).await?;
+            }
+        }
+        return Ok(());
+    }
+
     if hr {
-        add_headers(MeasureType::Hr, &paths.hr, metadata.to_string()).await?;
+        add_headers(MeasureType::Hr, &paths.hr, metadata.to_string(), settings).await?;
     }
 
     if ecg {
-        add_headers(MeasureType::Ecg, &paths.ecg, metadata.to_string()).await?;
+        add_headers(MeasureType::Ecg, &paths.ecg, metadata.to_string(), settings).await?;
     }
 
     if acc {
-        add_headers(MeasureType::Acc, &paths.acc, metadata.to_string()).await?;
+        add_headers(MeasureType::Acc, &paths.acc, metadata.to_string(), settings).await?;
     }
 
     Ok(())
 }
 
-// Add headers to each csv file
-async fn add_headers(ty: MeasureType, path: &str, mut msg: String) -> Result<(), Error> {
-    let output = OpenOptions::new()
+// Parquet files are only created once data arrives, since a file without rows would
+// have no footer. Creating and removing it here reports an unusable path before
+// connecting and clears out a file left by an older recording.
+async fn check_writable(path: &str) -> Result<(), Error> {
+    OpenOptions::new()
         .write(true)
         .truncate(true)
         .create(true)
         .open(path)
         .await?;
+    fs::remove_file(path).await?;
+
+    Ok(())
+}
+
+// Add headers to each csv file and clear out segments left by an older recording
+async fn add_headers(
+    ty: MeasureType,
+    path: &str,
+    mut msg: String,
+    settings: Setting,
+) -> Result<(), Error> {
+    let compression = settings.compression;
+    let mut index = 1;
+    while fs::metadata(segment_path(path, compression, index))
+        .await
+        .is_ok()
+    {
+        fs::remove_file(segment_path(path, compression, index)).await?;
+        index += 1;
+    }
+
+    let output = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(segment_path(path, compression, 0))
+        .await?;
     let mut writer = BufWriter::with_capacity(200, output);
-    msg.push_str(&ty.to_string());
+    msg.push_str(&column_header(ty, settings.rr_layout));
 
-    writer.write_all(msg.as_bytes()).await?;
+    let mut encoder = Encoder::new(compression)?;
+    let mut bytes = encoder.write(msg.as_bytes())?;
+    bytes.extend(encoder.finish()?);
+    writer.write_all(&bytes).await?;
     writer.flush().await?;
 
     Ok(())
 }
 
+// Version of the hr csv layout, written in the header of hr files
+//   1: `time,bpm,rr` followed by a variable number of rr columns
+//   2: `time,bpm,rr` with exactly one rr interval per row, or a quoted list of them
+pub const HR_SCHEMA_VERSION: u8 = 2;
+
+// Header line naming the columns, hr files also get a schema line before it
+fn column_header(ty: MeasureType, layout: RrLayout) -> String {
+    match ty {
+        MeasureType::Hr => {
+            let version = match layout {
+                RrLayout::Columns => 1,
+                _ => HR_SCHEMA_VERSION,
+            };
+            format!("#schema={};rr={}\n{}", version, layout.key(), ty)
+        }
+        _ => ty.to_string(),
+    }
+}
+
+// Create hr rows according to the chosen rr layout
+fn generate_hr_msg(timestamp: u64, bpm: u8, rr: &[u16], layout: RrLayout) -> String {
+    match layout {
+        RrLayout::Columns => {
+            let rr: String = rr.iter().map(|i| format!(",{}", i)).collect();
+            format!("{},{}{}\n", timestamp, bpm, rr)
+        }
+        RrLayout::List => {
+            let rr: Vec<_> = rr.iter().map(|i| i.to_string()).collect();
+            format!("{},{},\"{}\"\n", timestamp, bpm, rr.join(","))
+        }
+        RrLayout::Rows => {
+            if rr.is_empty() {
+                return format!("{},{},\n", timestamp, bpm);
+            }
+            // only the row at the notification's time has the bpm, so every
+            // reading is there once
+            let times = rr_timestamps(timestamp, rr);
+            let last = rr.len() - 1;
+            rr.iter()
+                .zip(times)
+                .enumerate()
+                .map(|(n, (i, end))| match n == last {
+                    true => format!("{},{},{}\n", end, bpm, i),
+                    false => format!("{},,{}\n", end, i),
+                })
+                .collect()
+        }
+    }
+}
+
+// When each RR interval ended. The last one ends when the notification arrives,
+// earlier ones end one interval before the next.
+pub fn rr_timestamps(timestamp: u64, rr: &[u16]) -> Vec<u64> {
+    let mut end = timestamp;
+    let mut times = vec![end];
+    for i in rr.iter().skip(1).rev() {
+        end = end.saturating_sub(*i as u64 * 1_000_000);
+        times.push(end);
+    }
+    times.truncate(rr.len());
+    times.reverse();
+    times
+}
+
+// Path of a csv segment, the first segment keeps the name the user chose
+pub fn segment_path(path: &str, compression: Compression, index: u32) -> String {
+    let path = if index == 0 {
+        path.to_string()
+    } else {
+        match path.rfind('.') {
+            Some(dot) if !path[dot..].contains('/') => {
+                format!("{}.{}{}", &path[..dot], index, &path[dot..])
+            }
+            _ => format!("{}.{}", path, index),
+        }
+    };
+    format!("{}{}", path, compression.extension())
+}
+
+// Compression stream of a segment, handing out the bytes that are ready for the file.
+// Gzip and zstd both allow concatenated streams, so the headers written before the
+// strap connected stay a stream of their own.
+enum Encoder {
+    Plain,
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(compression: Compression) -> Result<Self, Error> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain,
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(vec![], flate2::Compression::default()))
+            }
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(vec![], 0)?),
+        })
+    }
+
+    fn write(&mut self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoder::Plain => return Ok(msg.to_vec()),
+            Encoder::Gzip(encoder) => encoder.write_all(msg)?,
+            Encoder::Zstd(encoder) => encoder.write_all(msg)?,
+        }
+        Ok(self.take())
+    }
+
+    // Everything written so far, so it can be read before the stream ends
+    fn flush(&mut self) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoder::Plain => {}
+            Encoder::Gzip(encoder) => encoder.flush()?,
+            Encoder::Zstd(encoder) => encoder.flush()?,
+        }
+        Ok(self.take())
+    }
+
+    fn finish(self) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoder::Plain => Ok(vec![]),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            Encoder::Plain => vec![],
+            Encoder::Gzip(encoder) => std::mem::take(encoder.get_mut()),
+            Encoder::Zstd(encoder) => std::mem::take(encoder.get_mut()),
+        }
+    }
+}
+
+// Compressed data is flushed to the file this often
+const FLUSH: Duration = Duration::from_secs(1);
+
+// Open segment file and its compression stream
+struct Output {
+    path: String,
+    file: File,
+    encoder: Encoder,
+    // bytes in the file so far, for rotating by size
+    size: u64,
+    flushed: Instant,
+}
+
+impl Output {
+    async fn open(path: String, compression: Compression) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            file,
+            encoder: Encoder::new(compression)?,
+            size,
+            flushed: Instant::now(),
+        })
+    }
+
+    async fn write(&mut self, msg: &str) -> Result<(), Error> {
+        let mut bytes = self.encoder.write(msg.as_bytes())?;
+        if self.flushed.elapsed() >= FLUSH {
+            bytes.extend(self.encoder.flush()?);
+            self.flushed = Instant::now();
+        }
+        self.write_bytes(&bytes).await
+    }
+
+    async fn finish(mut self) -> Result<(), Error> {
+        let encoder = std::mem::replace(&mut self.encoder, Encoder::Plain);
+        self.write_bytes(&encoder.finish()?).await
+    }
+
+    async fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(bytes).await?;
+        self.file.flush().await?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+// Segment currently written to for a stream, its file stays open until the segment is
+// rotated or measurement stops
+struct Segment {
+    index: u32,
+    opened: Instant,
+    output: Option<Output>,
+}
+
+// Keep track of compression, rotation and layout for csv output
+pub struct CsvWriter {
+    metadata: String,
+    // `#device=...` and `#pmd=...` lines, only known once the strap is connected
+    sensor: String,
+    compression: Compression,
+    rotation: Rotation,
+    rr_layout: RrLayout,
+    segments: tokio::sync::Mutex<[Segment; 3]>,
+}
+
+impl CsvWriter {
+    pub fn new(
+        metadata: &Meta,
+        Setting {
+            compression,
+            rotation,
+            rr_layout,
+            ..
+        }: Setting,
+    ) -> Self {
+        let segment = || Segment {
+            index: 0,
+            opened: Instant::now(),
+            output: None,
+        };
+        Self {
+            metadata: metadata.to_string(),
+            sensor: format!(
+                "{}{}",
+                metadata
+                    .device
+                    .as_ref()
+                    .map(|d| d.to_string())
+                    .unwrap_or_default(),
+                metadata.pmd.map(|p| p.to_string()).unwrap_or_default()
+            ),
+            compression,
+            rotation,
+            rr_layout,
+            segments: tokio::sync::Mutex::new([segment(), segment(), segment()]),
+        }
+    }
+
+    // Append msg to the current segment of a stream, starting a new one if needed
+    async fn append(&self, ty: MeasureType, path: &str, msg: &str) -> Result<(), Error> {
+        let mut segments = self.segments.lock().await;
+        let segment = &mut segments[ty.index()];
+        if segment.output.is_none() {
+            let current = segment_path(path, self.compression, segment.index);
+            segment.output = Some(Output::open(current, self.compression).await?);
+        }
+        let output = segment.output.as_mut().expect("opened above");
+
+        let rotate = match self.rotation {
+            Rotation::Never => false,
+            Rotation::Size(mb) => output.size >= mb * 1024 * 1024,
+            Rotation::Time(min) => segment.opened.elapsed() >= Duration::from_secs(min * 60),
+        };
+        if !rotate {
+            return output.write(msg).await;
+        }
+
+        if let Some(output) = segment.output.take() {
+            output.finish().await?;
+        }
+        segment.index += 1;
+        segment.opened = Instant::now();
+        let next = segment_path(path, self.compression, segment.index);
+        let output = segment
+            .output
+            .insert(Output::open(next, self.compression).await?);
+        // every segment gets its own headers so it can be read on its own
+        output
+            .write(&format!(
+                "{}{}{}{}",
+                self.metadata,
+                column_header(ty, self.rr_layout),
+                self.sensor,
+                msg
+            ))
+            .await
+    }
+
+    // Every segment written for a stream, oldest first
+    pub async fn segments(&self, ty: MeasureType, path: &str) -> Vec<String> {
+        let last = self.segments.lock().await[ty.index()].index;
+        (0..=last)
+            .map(|index| segment_path(path, self.compression, index))
+            .collect()
+    }
+
+    // End the compression streams once measurement stops
+    pub async fn finish(&self) -> Result<(), Error> {
+        let mut segments = self.segments.lock().await;
+        for segment in segments.iter_mut() {
+            if let Some(output) = segment.output.take() {
+                output.finish().await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Add device information and measurement settings under the column names of
+    // a file that was created before the strap connected
+    pub async fn sensor_info(&self, ty: MeasureType, path: &str) -> Result<(), Error> {
+        if self.sensor.is_empty() {
+            return Ok(());
+        }
+        self.append(ty, path, &self.sensor).await
+    }
+
+    // Event like the start of a protocol phase, at its time on the session clock
+    pub async fn marker(
+        &self,
+        ty: MeasureType,
+        path: &str,
+        time: u64,
+        label: &str,
+    ) -> Result<(), Error> {
+        self.append(
+            ty,
+            path,
+            &format!("#marker;time={};label={}\n", time, label),
+        )
+        .await
+    }
+
+    // How long data was recorded for, written once measurement stops
+    pub async fn duration(&self, ty: MeasureType, path: &str, secs: f64) -> Result<(), Error> {
+        self.append(ty, path, &format!("#duration={:.3}\n", secs))
+            .await
+    }
+
+    // Mark where data is missing, with the number of missing samples when it is known
+    pub async fn gap(&self, ty: MeasureType, path: &str, missing: u64) -> Result<(), Error> {
+        let line = match missing {
+            0 => "#gap\n".to_string(),
+            n => format!("#gap;missing={}\n", n),
+        };
+        self.append(ty, path, &line).await
+    }
+}
+
+// Streams still open when the handler goes away without stopping, e.g. after the strap
+// disconnected, are ended so the files stay readable
+impl Drop for CsvWriter {
+    fn drop(&mut self) {
+        for segment in self.segments.get_mut().iter_mut() {
+            if let Some(output) = segment.output.take() {
+                let res = output.encoder.finish().and_then(|bytes| {
+                    std::fs::OpenOptions::new()
+                        .append(true)
+                        .open(&output.path)?
+                        .write_all(&bytes)
+                });
+                if let Err(e) = res {
+                    log::error!("Error closing {}: {}", output.path, e);
+                }
+            }
+        }
+    }
+}
+
 // Write ecg/acc data to file return last data for sending
 pub async fn write_data(
-    data: PmdRead,
-    rate: u8,
+    ty: MeasureType,
+    samples: Vec<(u64, Sample)>,
     paths: &Paths,
-    start: &Mutex<Option<u64>>,
+    csv: &CsvWriter,
 ) -> Result<Option<(i16, i16, i16)>, Error> {
-    let outpath = match data.data_type() {
-        H10MeasurementType::Acc => &paths.acc,
-        H10MeasurementType::Ecg => &paths.ecg,
+    let outpath = match ty {
+        MeasureType::Acc => &paths.acc,
+        _ => &paths.ecg,
     };
 
-    let outfile = OpenOptions::new().append(true).open(outpath).await?;
-
-    let mut writer = BufWriter::with_capacity(400, outfile);
-    let msg = generate_msg(data, rate, start);
-
-    writer.write_all(msg.0.as_bytes()).await?;
-    writer.flush().await?;
+    let msg = generate_msg(samples);
+    csv.append(ty, outpath, &msg.0).await?;
 
     Ok(msg.1)
 }
 
 // Create msg to write to csv file
-fn generate_msg(
-    data: PmdRead,
-    rate: u8,
-    start: &Mutex<Option<u64>>,
-) -> (String, Option<(i16, i16, i16)>) {
+fn generate_msg(samples: Vec<(u64, Sample)>) -> (String, Option<(i16, i16, i16)>) {
     let mut msg = "".to_string();
-    let mut timestamp = data.time_stamp();
+    let mut last = None;
 
-    // change in timestamp between samples
-    let offset = (1.0
-        / (match data.data_type() {
-            H10MeasurementType::Acc => rate,
-            H10MeasurementType::Ecg => 130,
-        } as f64
-            * 1.0e-9)) as u64; // convert hz to ns
+    for (timestamp, sample) in samples {
+        match sample {
+            Sample::Acc(x, y, z) => {
+                msg.push_str(format!("{},{},{},{}\n", timestamp, x, y, z).as_str());
+                last = Some((x, y, z));
+            }
+            Sample::Ecg(val) => {
+                msg.push_str(format!("{},{}\n", timestamp, val).as_str());
+            }
+        }
+    }
 
-    let (mut x, mut y, mut z) = (0, 0, 0);
-    let ty = *data.data_type();
+    (msg, last)
+}
 
-    let mut first = start.lock().expect("stupid mutex");
-    timestamp = if let Some(st) = first.as_ref() {
-        timestamp - *st
-    } else {
-        *first = Some(timestamp);
-        0
-    };
+// Nominal time between two samples in ns
+pub fn sample_period(ty: &H10MeasurementType, rate: u8) -> u64 {
+    (1.0 / (match ty {
+        H10MeasurementType::Acc => rate,
+        H10MeasurementType::Ecg => ECG_RATE,
+    } as f64
+        * 1.0e-9)) as u64 // convert hz to ns
+}
 
-    for d in data.data() {
-        match d {
+// Decode the samples of a frame
+pub fn frame_samples(data: PmdRead) -> Vec<Sample> {
+    data.data()
+        .into_iter()
+        .map(|d| match d {
             PmdData::Acc(acc) => {
-                (x, y, z) = acc.data();
-                msg.push_str(format!("{},{},{},{}\n", timestamp, x, y, z).as_str());
-            }
-            PmdData::Ecg(ecg) => {
-                msg.push_str(format!("{},{}\n", timestamp, ecg.val()).as_str());
+                let (x, y, z) = acc.data();
+                Sample::Acc(x as i16, y as i16, z as i16)
             }
-        }
-        timestamp += offset;
+            PmdData::Ecg(ecg) => Sample::Ecg(*ecg.val()),
+        })
+        .collect()
+}
+
+// Time between frames may differ this much (as a fraction) from the nominal rate before
+// it is assumed something went wrong and the nominal rate is used instead
+const MAX_DRIFT: f64 = 0.1;
+
+// Sensor time of the first sample in a frame and the time between its samples in ns.
+// `previous` is the timestamp of the frame before, when no samples were lost in between.
+pub fn frame_timing(
+    timing: Timing,
+    time_stamp: u64,
+    previous: Option<u64>,
+    samples: usize,
+    period: u64,
+) -> (u64, f64) {
+    if timing == Timing::Nominal || samples == 0 {
+        return (time_stamp, period as f64);
     }
 
-    let last = match ty {
-        H10MeasurementType::Acc => Some((x as i16, y as i16, z as i16)),
-        _ => None,
+    let nominal = period as f64;
+    let spacing = match previous {
+        Some(prev) if prev < time_stamp => {
+            let spacing = (time_stamp - prev) as f64 / samples as f64;
+            if (spacing - nominal).abs() <= nominal * MAX_DRIFT {
+                spacing
+            } else {
+                nominal
+            }
+        }
+        _ => nominal,
     };
+    // the frame timestamp belongs to its last sample
+    let first = time_stamp.saturating_sub(((samples - 1) as f64 * spacing).round() as u64);
 
-    (msg, last)
+    (first, spacing)
 }
 
-const DIFF_FROM_H10_TO_UNIX: u64 = 946684800000000000;
-
-// Write hr data
-pub async fn write_hr(
-    data: HeartRate,
-    path: &str,
+// Give every sample in a frame a timestamp relative to the start of the session
+pub fn timestamp_samples(
+    first: u64,
+    spacing: f64,
+    samples: Vec<Sample>,
     start: &Mutex<Option<u64>>,
-) -> Result<(u8, String), Error> {
-    let outfile = OpenOptions::new().append(true).open(path).await?;
+) -> Vec<(u64, Sample)> {
+    let mut origin = start.lock().expect("stupid mutex");
+    let origin = *origin.get_or_insert(first);
+
+    samples
+        .into_iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            let time = first + (i as f64 * spacing).round() as u64;
+            (time.saturating_sub(origin), sample)
+        })
+        .collect()
+}
 
+const DIFF_FROM_H10_TO_UNIX: u64 = 946684800000000000;
+
+// Timestamp for hr data relative to the first heart rate update
+pub fn hr_timestamp(start: &Mutex<Option<u64>>) -> u64 {
     let unix = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards????????");
 
-    let mut timestamp = unix.as_nanos() - DIFF_FROM_H10_TO_UNIX as u128;
+    let timestamp = (unix.as_nanos() - DIFF_FROM_H10_TO_UNIX as u128) as u64;
 
-    // so mutex goes out of scope
-    {
-        let mut first = start.lock().expect("stupid mutex");
-        timestamp = if let Some(st) = first.as_ref() {
-            timestamp - *st as u128
-        } else {
-            *first = Some(timestamp as u64);
-            0
-        };
+    let mut first = start.lock().expect("stupid mutex");
+    if let Some(st) = first.as_ref() {
+        timestamp - *st
+    } else {
+        *first = Some(timestamp);
+        0
     }
+}
+
+// Write hr data
+pub async fn write_hr(
+    data: HeartRate,
+    path: &str,
+    start: &Mutex<Option<u64>>,
+    csv: &CsvWriter,
+) -> Result<(u8, String), Error> {
+    let timestamp = hr_timestamp(start);
 
     let mut rr = "".to_string();
     let stupid = vec![]; // unwanted silly empty array
@@ -169,11 +653,8 @@ pub async fn write_hr(
         rr.push_str(format!(",{}", i).as_str());
     }
 
-    let mut writer = BufWriter::with_capacity(200, outfile);
-    let msg = format!("{},{}{}\n", timestamp, data.bpm(), rr.clone());
-
-    writer.write_all(msg.as_bytes()).await?;
-    writer.flush().await?;
+    let msg = generate_hr_msg(timestamp, *data.bpm(), rr_data, csv.rr_layout);
+    csv.append(MeasureType::Hr, path, &msg).await?;
 
     Ok((*data.bpm(), rr))
 }
@@ -181,11 +662,20 @@ pub async fn write_hr(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Read;
+    use std::path::Path;
+
+    // Timestamp a frame the way it was done before interpolation
+    fn nominal(data: PmdRead, rate: u8, start: &Mutex<Option<u64>>) -> Vec<(u64, Sample)> {
+        let period = sample_period(data.data_type(), rate);
+        let (first, spacing) = frame_timing(Timing::Nominal, data.time_stamp(), None, 0, period);
+        timestamp_samples(first, spacing, frame_samples(data), start)
+    }
 
     #[test]
     fn try_get_msg_ecg() {
         let prev = Mutex::new(Some(0));
-        let msg = generate_msg(
+        let msg = generate_msg(nominal(
             PmdRead::new(vec![
                 0x00, 0xea, 0x54, 0xa2, 0x42, 0x8b, 0x45, 0x52, 0x08, 0x00, 0xff, 0xff, 0xff, 0x00,
                 0x00, 0x10,
@@ -193,7 +683,7 @@ mod tests {
             .unwrap(),
             200,
             &prev,
-        );
+        ));
         let timestamp = 599618164814402794u64;
         let new_time = timestamp + 7692307;
 
@@ -204,7 +694,7 @@ mod tests {
     #[test]
     fn try_get_msg_acc() {
         let prev = Mutex::new(Some(0));
-        let msg = generate_msg(
+        let msg = generate_msg(nominal(
             PmdRead::new(vec![
                 0x02, 0xea, 0x54, 0xa2, 0x42, 0x8b, 0x45, 0x52, 0x08, 0x01, 0x45, 0xff, 0xe4, 0xff,
                 0xb5, 0x03, 0x45, 0xff, 0xe4, 0xff, 0xb8, 0x03,
@@ -212,7 +702,7 @@ mod tests {
             .unwrap(),
             200,
             &prev,
-        );
+        ));
 
         let timestamp = 599618164814402794u64;
         let new_time = timestamp + 5000000;
@@ -220,4 +710,115 @@ mod tests {
         assert!(msg.0.contains(&format!("{}", timestamp)));
         assert!(msg.0.contains(&format!("{}", new_time)));
     }
+
+    #[test]
+    fn interpolated_times() {
+        let period = 5_000_000;
+        // a sensor clock running 1% slow spreads 4 samples over 20.2 ms
+        let (first, spacing) = frame_timing(
+            Timing::Interpolated,
+            1_020_200_000,
+            Some(1_000_000_000),
+            4,
+            period,
+        );
+        assert_eq!(spacing, 5_050_000.0);
+        assert_eq!(first, 1_005_050_000);
+
+        let start = Mutex::new(Some(1_000_000_000));
+        let times: Vec<_> = timestamp_samples(first, spacing, vec![Sample::Ecg(0); 4], &start)
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+        assert_eq!(times, [5_050_000, 10_100_000, 15_150_000, 20_200_000]);
+
+        // without a usable previous frame the nominal rate ends at the frame timestamp
+        assert_eq!(
+            frame_timing(Timing::Interpolated, 1_000_000_000, None, 4, period),
+            (985_000_000, 5_000_000.0)
+        );
+        assert_eq!(
+            frame_timing(
+                Timing::Interpolated,
+                1_040_000_000,
+                Some(1_000_000_000),
+                4,
+                period
+            ),
+            (1_025_000_000, 5_000_000.0)
+        );
+    }
+
+    #[test]
+    fn hr_layouts() {
+        let rr = [1000, 500];
+        assert_eq!(
+            generate_hr_msg(3_000_000_000, 60, &rr, RrLayout::Columns),
+            "3000000000,60,1000,500\n"
+        );
+        assert_eq!(
+            generate_hr_msg(3_000_000_000, 60, &rr, RrLayout::List),
+            "3000000000,60,\"1000,500\"\n"
+        );
+        assert_eq!(
+            generate_hr_msg(3_000_000_000, 60, &rr, RrLayout::Rows),
+            "2500000000,,1000\n3000000000,60,500\n"
+        );
+        assert_eq!(generate_hr_msg(0, 60, &[], RrLayout::Rows), "0,60,\n");
+        assert_eq!(generate_hr_msg(0, 60, &[], RrLayout::List), "0,60,\"\"\n");
+    }
+
+    #[test]
+    fn segment_names() {
+        assert_eq!(
+            segment_path("out/ecg.csv", Compression::None, 0),
+            "out/ecg.csv"
+        );
+        assert_eq!(
+            segment_path("out/ecg.csv", Compression::Gzip, 2),
+            "out/ecg.2.csv.gz"
+        );
+        assert_eq!(
+            segment_path("out.d/ecg", Compression::Zstd, 1),
+            "out.d/ecg.1.zst"
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_rotation() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("ecg.csv");
+            let path = path.to_str().unwrap();
+
+            let settings = Setting {
+                compression,
+                rotation: Rotation::Size(0),
+                ..Setting::default()
+            };
+            add_headers(MeasureType::Ecg, path, "meta\n".to_string(), settings)
+                .await
+                .unwrap();
+            let csv = CsvWriter::new(&Meta::default(), settings);
+            csv.append(MeasureType::Ecg, path, "1,10\n2,20\n")
+                .await
+                .unwrap();
+            csv.append(MeasureType::Ecg, path, "3,30\n").await.unwrap();
+            csv.finish().await.unwrap();
+
+            assert!(!Path::new(&segment_path(path, compression, 3)).exists());
+            let file = std::fs::File::open(segment_path(path, compression, 2)).unwrap();
+            let mut text = String::new();
+            match compression {
+                Compression::Gzip => flate2::read::MultiGzDecoder::new(file)
+                    .read_to_string(&mut text)
+                    .unwrap(),
+                _ => zstd::Decoder::new(file)
+                    .unwrap()
+                    .read_to_string(&mut text)
+                    .unwrap(),
+            };
+            assert!(text.ends_with("time,val\n3,30\n"));
+        }
+    }
 }
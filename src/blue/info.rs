@@ -0,0 +1,132 @@
+use super::scan::parse_name;
+use arctic::Error;
+use btleplug::api::{bleuuid::uuid_from_u16, Central, Manager as _, Peripheral as _};
+use btleplug::platform::{Manager, Peripheral};
+use std::fmt;
+use uuid::Uuid;
+
+// Battery levels at or below this are worth charging before a long session
+pub const LOW_BATTERY: u8 = 20;
+
+// Standard Battery and Device Information characteristics
+const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2a19);
+const MODEL_NUMBER: Uuid = uuid_from_u16(0x2a24);
+const SERIAL_NUMBER: Uuid = uuid_from_u16(0x2a25);
+const FIRMWARE_REVISION: Uuid = uuid_from_u16(0x2a26);
+const MANUFACTURER_NAME: Uuid = uuid_from_u16(0x2a29);
+
+// What the strap reports about itself
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub battery: Option<u8>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn low_battery(&self) -> bool {
+        matches!(self.battery, Some(level) if level <= LOW_BATTERY)
+    }
+}
+
+// Written as a `#device=...` line in csv files
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = |s: &Option<String>| s.clone().unwrap_or_default().replace([',', ';'], "-");
+        writeln!(
+            f,
+            "#device={};battery={};manufacturer={};model={};firmware={};serial={}",
+            self.id,
+            self.battery.map(|b| b.to_string()).unwrap_or_default(),
+            text(&self.manufacturer),
+            text(&self.model),
+            text(&self.firmware),
+            text(&self.serial),
+        )
+    }
+}
+
+// Find the already connected peripheral for a device id
+async fn find(id: &str) -> Result<Peripheral, Error> {
+    let manager = Manager::new().await.map_err(Error::BleError)?;
+    let central = manager
+        .adapters()
+        .await
+        .map_err(Error::BleError)?
+        .into_iter()
+        .next()
+        .ok_or(Error::NoBleAdaptor)?;
+
+    for p in central.peripherals().await.map_err(Error::BleError)? {
+        let name = match p.properties().await {
+            Ok(Some(props)) => props.local_name.unwrap_or_default(),
+            _ => continue,
+        };
+        if parse_name(&name).as_deref() == Some(id) {
+            return Ok(p);
+        }
+    }
+
+    Err(Error::NoDevice)
+}
+
+// Read the Battery and Device Information services of a connected strap
+pub async fn read_info(id: &str) -> Result<DeviceInfo, Error> {
+    let device = find(id).await?;
+    device.discover_services().await.map_err(Error::BleError)?;
+    let characteristics = device.characteristics();
+
+    let read = |uuid: Uuid| {
+        let device = &device;
+        let characteristic = characteristics.iter().find(|c| c.uuid == uuid).cloned();
+        async move {
+            match characteristic {
+                Some(c) => device.read(&c).await.ok(),
+                None => None,
+            }
+        }
+    };
+    let text = |bytes: Option<Vec<u8>>| {
+        bytes.map(|b| {
+            String::from_utf8_lossy(&b)
+                .trim_end_matches('\0')
+                .to_string()
+        })
+    };
+
+    Ok(DeviceInfo {
+        id: id.to_string(),
+        battery: read(BATTERY_LEVEL).await.and_then(|b| b.first().copied()),
+        manufacturer: text(read(MANUFACTURER_NAME).await),
+        model: text(read(MODEL_NUMBER).await),
+        firmware: text(read(FIRMWARE_REVISION).await),
+        serial: text(read(SERIAL_NUMBER).await),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_line() {
+        let info = DeviceInfo {
+            id: "7B45F72B".to_string(),
+            battery: Some(15),
+            model: Some("H10".to_string()),
+            firmware: Some("3.1.1".to_string()),
+            serial: Some("C9,1".to_string()),
+            ..DeviceInfo::default()
+        };
+
+        assert!(info.low_battery());
+        assert_eq!(
+            info.to_string(),
+            "#device=7B45F72B;battery=15;manufacturer=;model=H10;firmware=3.1.1;serial=C9-1\n"
+        );
+        assert!(!DeviceInfo::default().low_battery());
+    }
+}
@@ -0,0 +1,193 @@
+use super::fs::MeasureType;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// How often a stream that keeps losing frames is reported again
+const SUMMARY_EVERY: Duration = Duration::from_secs(60);
+
+// Samples received and missed by one PMD stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamLoss {
+    pub received: u64,
+    pub missing: u64,
+    // sensor time of the newest frame
+    last: Option<u64>,
+}
+
+impl StreamLoss {
+    pub fn percent(&self) -> f64 {
+        match self.received + self.missing {
+            0 => 0.0,
+            total => self.missing as f64 * 100.0 / total as f64,
+        }
+    }
+
+    // Frame timestamps belong to the last sample of the frame, so a frame that
+    // arrives more than its own length after the previous one means samples were dropped
+    fn frame(&mut self, time_stamp: u64, samples: u64, period: u64) -> u64 {
+        let missing = match self.last {
+            Some(last) if time_stamp > last && period > 0 => {
+                let spanned = ((time_stamp - last) as f64 / period as f64).round() as u64;
+                spanned.saturating_sub(samples)
+            }
+            _ => 0,
+        };
+        self.last = Some(time_stamp);
+        self.received += samples;
+        self.missing += missing;
+        missing
+    }
+}
+
+impl fmt::Display for StreamLoss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% ({} of {} samples missing)",
+            self.percent(),
+            self.missing,
+            self.received + self.missing
+        )
+    }
+}
+
+// Data loss of the PMD streams of one strap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Loss {
+    pub ecg: StreamLoss,
+    pub acc: StreamLoss,
+}
+
+impl Loss {
+    // Count a frame of `samples` samples taken `period` ns apart, returns how many samples
+    // went missing before it
+    pub fn frame(&mut self, ty: MeasureType, time_stamp: u64, samples: u64, period: u64) -> u64 {
+        match ty {
+            MeasureType::Ecg => self.ecg.frame(time_stamp, samples, period),
+            MeasureType::Acc => self.acc.frame(time_stamp, samples, period),
+            MeasureType::Hr => 0,
+        }
+    }
+
+    // Sensor time of the newest frame of a stream
+    pub fn last(&self, ty: MeasureType) -> Option<u64> {
+        match ty {
+            MeasureType::Ecg => self.ecg.last,
+            MeasureType::Acc => self.acc.last,
+            MeasureType::Hr => None,
+        }
+    }
+}
+
+// What to tell the user about missing samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossWarning {
+    // a stream started losing frames, with the samples missing before this frame
+    Started(u64),
+    // samples missed since the last warning
+    Summary(u64),
+}
+
+// Keeps missing samples from warning on every frame
+#[derive(Debug, Default)]
+pub struct LossWarnings {
+    // per stream, when it was last warned about and samples missed since
+    streams: [Option<(Instant, u64)>; 3],
+}
+
+impl LossWarnings {
+    // Count the samples missing before a frame, returns a warning if one is due
+    pub fn frame(&mut self, ty: MeasureType, missing: u64, now: Instant) -> Option<LossWarning> {
+        let stream = &mut self.streams[ty.index()];
+        match stream {
+            // a stream that was fine for a while starts over
+            Some((warned, 0)) if missing > 0 && now - *warned >= SUMMARY_EVERY => {
+                *stream = Some((now, 0));
+                Some(LossWarning::Started(missing))
+            }
+            Some((warned, pending)) => {
+                *pending += missing;
+                if *pending > 0 && now - *warned >= SUMMARY_EVERY {
+                    let pending = *pending;
+                    *stream = Some((now, 0));
+                    Some(LossWarning::Summary(pending))
+                } else {
+                    None
+                }
+            }
+            None if missing > 0 => {
+                *stream = Some((now, 0));
+                Some(LossWarning::Started(missing))
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_samples() {
+        let mut loss = Loss::default();
+        // 73 ECG samples per frame at 130 Hz
+        let period = 7_692_307;
+        assert_eq!(loss.frame(MeasureType::Ecg, 1_000_000_000, 73, period), 0);
+        assert_eq!(
+            loss.frame(MeasureType::Ecg, 1_000_000_000 + 73 * period, 73, period),
+            0
+        );
+        // one frame dropped
+        assert_eq!(
+            loss.frame(MeasureType::Ecg, 1_000_000_000 + 219 * period, 73, period),
+            73
+        );
+        // a slightly fast sensor clock doesn't count as loss
+        assert_eq!(
+            loss.frame(
+                MeasureType::Ecg,
+                1_000_000_000 + 292 * period - 1000,
+                73,
+                period
+            ),
+            0
+        );
+
+        assert_eq!(loss.ecg.received, 292);
+        assert_eq!(loss.ecg.missing, 73);
+        assert_eq!(loss.ecg.percent(), 20.0);
+        assert_eq!(loss.acc, StreamLoss::default());
+    }
+
+    #[test]
+    fn throttled_warnings() {
+        let mut warnings = LossWarnings::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(warnings.frame(MeasureType::Ecg, 0, at(0)), None);
+        assert_eq!(
+            warnings.frame(MeasureType::Ecg, 73, at(1)),
+            Some(LossWarning::Started(73))
+        );
+        assert_eq!(warnings.frame(MeasureType::Ecg, 73, at(2)), None);
+        assert_eq!(warnings.frame(MeasureType::Ecg, 73, at(30)), None);
+        // the other stream is warned about on its own
+        assert_eq!(
+            warnings.frame(MeasureType::Acc, 36, at(30)),
+            Some(LossWarning::Started(36))
+        );
+        // summary once the period is over, even from a frame without loss
+        assert_eq!(
+            warnings.frame(MeasureType::Ecg, 0, at(61)),
+            Some(LossWarning::Summary(146))
+        );
+        assert_eq!(warnings.frame(MeasureType::Ecg, 0, at(200)), None);
+        // losing frames again after a quiet period warns right away
+        assert_eq!(
+            warnings.frame(MeasureType::Ecg, 73, at(201)),
+            Some(LossWarning::Started(73))
+        );
+    }
+}
@@ -1,36 +1,277 @@
+pub mod event;
 pub mod fs;
+pub mod info;
+pub mod loss;
+pub mod parquet;
+pub mod raw;
+pub mod scan;
 pub mod setting;
+pub mod sqlite;
 
 use crate::{
     data::DataReceiver,
     menu::{Meta, Paths},
+    recording::Source,
 };
 use arctic::{
     async_trait, Error, EventHandler, H10MeasurementType, HeartRate, NotifyStream, PmdRead,
     PolarSensor,
 };
-use fs::{init, write_data, write_hr};
-use setting::Setting;
-use std::sync::{self, Arc};
+use chrono::Utc;
+use event::Events;
+use fs::{
+    frame_samples, frame_timing, hr_timestamp, init, sample_period, timestamp_samples, write_data,
+    write_hr, CsvWriter, MeasureType, Sample,
+};
+use info::{read_info, DeviceInfo};
+use loss::{Loss, LossWarning, LossWarnings};
+use parquet::Sinks;
+use raw::{FrameKind, RawLog};
+use setting::{check_supported, Format, PmdSettings, Setting, Timing};
+use sqlite::Database;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{
+    self,
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
 use tokio::sync::{
+    oneshot,
     watch::{channel, Receiver, Sender},
     Mutex,
 };
 
+// Wait before the first retry, doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(16);
+
+// Time origin shared by every strap recording in the same session
+pub type SessionClock = Arc<sync::Mutex<Option<u64>>>;
+
+// Event loops shorter than this right after reconnecting count as failed reconnects
+const MIN_SESSION: Duration = Duration::from_secs(5);
+const MAX_FAILED_RECONNECTS: u8 = 3;
+
 // manage Bluetooth connections
 #[derive(Default)]
 pub struct SensorManager {
     pub sensor: Option<PolarSensor>,
+    pub link: Option<Link>,
 }
 
 impl SensorManager {
     pub async fn start(&mut self) -> Result<(), Error> {
-        if let Some(sensor) = &mut self.sensor {
-            sensor.event_loop().await?;
-            Ok(())
-        } else {
-            Err(Error::NoDevice)
+        let sensor = self.sensor.as_mut().ok_or(Error::NoDevice)?;
+        let link = match &self.link {
+            Some(link) => link,
+            None => {
+                sensor.event_loop().await?;
+                return Ok(());
+            }
+        };
+
+        let mut failed = 0;
+        loop {
+            let started = Instant::now();
+            let res = sensor.event_loop().await;
+
+            // measurement was stopped on purpose
+            if !*link.running.borrow() {
+                return res;
+            }
+
+            failed = if started.elapsed() < MIN_SESSION {
+                failed + 1
+            } else {
+                0
+            };
+            if failed > MAX_FAILED_RECONNECTS {
+                link.set_state(ConnectionState::Lost);
+                link.events.error(format!(
+                    "Connection lost, it dropped {} times right after reconnecting",
+                    failed
+                ));
+                return res.and(Err(Error::NotConnected));
+            }
+
+            link.set_state(ConnectionState::Reconnecting);
+            link.events.warn(match &res {
+                Ok(()) => "Connection dropped, reconnecting".to_string(),
+                Err(e) => format!("Connection dropped ({}), reconnecting", e),
+            });
+            if let Err(e) = reconnect(sensor, link).await {
+                link.set_state(ConnectionState::Lost);
+                link.status(e.to_string());
+                link.events.error(format!("Connection lost: {}", e));
+                return Err(Error::NotConnected);
+            }
+            link.gaps.mark_all();
+            link.set_state(ConnectionState::Connected);
+            link.events
+                .info("Reconnected, the missing data is marked as a gap".to_string());
+        }
+    }
+}
+
+impl SensorManager {
+    // Record the next trial on a strap that is still connected, with new files and a new
+    // session clock. Waits for the last recording to stop.
+    pub async fn next_trial(
+        &mut self,
+        rx: Receiver<bool>,
+        done: oneshot::Sender<Source>,
+        trial: String,
+        paths: Paths,
+        clock: SessionClock,
+    ) -> Result<(), ConnectError> {
+        let sensor = self.sensor.as_mut().ok_or(Error::NoDevice)?;
+        let link = self.link.as_mut().ok_or(Error::NoDevice)?;
+        let metadata = Meta {
+            trial,
+            date: Utc::now(),
+            ..link.metadata.clone()
+        };
+        update(link.settings, metadata.clone(), paths.clone())
+            .await
+            .map_err(ConnectError::Output)?;
+
+        link.running = rx.clone();
+        let handler = Handler::new(
+            rx,
+            done,
+            link.settings,
+            metadata,
+            paths,
+            Arc::clone(&link.sender),
+            Arc::clone(&link.gaps),
+            clock,
+            Arc::clone(&link.markers),
+        );
+        if link.settings.format == Format::Csv {
+            handler.write_sensor_info().await;
+        }
+        sensor.event_handler(handler);
+        Ok(())
+    }
+}
+
+// Connect again and subscribe to the same streams as before
+async fn reconnect(sensor: &mut PolarSensor, link: &Link) -> Result<(), ConnectError> {
+    let timeout = Duration::from_secs(link.settings.timeout as u64);
+    tokio::time::timeout(
+        timeout,
+        connect(sensor, &link.id, link.settings.attempts, &link.status),
+    )
+    .await
+    .unwrap_or(Err(ConnectError::TimedOut(timeout)))?;
+
+    subscribe(sensor, &link.id, link.settings).await?;
+    Ok(())
+}
+
+async fn subscribe(sensor: &PolarSensor, id: &str, settings: Setting) -> Result<(), Error> {
+    if settings.hr {
+        sensor.subscribe(NotifyStream::HeartRate).await?;
+        log::info!("{}: subscribed to heart rate", id);
+    }
+    if settings.ecg || settings.acc {
+        sensor.subscribe(NotifyStream::MeasurementData).await?;
+        log::info!("{}: subscribed to measurement data", id);
+    }
+    Ok(())
+}
+
+// State of the connection to the sensor, shown on the data screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Connected,
+    Reconnecting,
+    Lost,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionState::Disconnected => "not connected",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Reconnecting => "reconnecting...",
+            ConnectionState::Lost => "connection lost",
+        })
+    }
+}
+
+// Streams that missed data because the connection dropped
+#[derive(Default)]
+pub struct Gaps {
+    hr: AtomicBool,
+    ecg: AtomicBool,
+    acc: AtomicBool,
+    raw: AtomicBool,
+}
+
+impl Gaps {
+    fn mark_all(&self) {
+        for flag in [&self.hr, &self.ecg, &self.acc, &self.raw] {
+            flag.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn take(&self, ty: MeasureType) -> bool {
+        match ty {
+            MeasureType::Hr => &self.hr,
+            MeasureType::Ecg => &self.ecg,
+            MeasureType::Acc => &self.acc,
         }
+        .swap(false, Ordering::SeqCst)
+    }
+
+    fn take_raw(&self) -> bool {
+        self.raw.swap(false, Ordering::SeqCst)
+    }
+}
+
+// Event markers waiting to be written, with their time on the session clock
+#[derive(Default)]
+pub struct Markers(sync::Mutex<Vec<(u64, String)>>);
+
+impl Markers {
+    pub fn push(&self, time: u64, label: String) {
+        self.0.lock().expect("stupid mutex").push((time, label));
+    }
+
+    fn take(&self) -> Vec<(u64, String)> {
+        std::mem::take(&mut *self.0.lock().expect("stupid mutex"))
+    }
+}
+
+// What the sensor manager needs to bring a dropped connection back
+pub struct Link {
+    id: String,
+    running: Receiver<bool>,
+    settings: Setting,
+    status: Arc<Sender<String>>,
+    state: Arc<Sender<ConnectionState>>,
+    gaps: Arc<Gaps>,
+    events: Events,
+    // what a new handler needs to record the next trial
+    metadata: Meta,
+    sender: Arc<DataSender>,
+    markers: Arc<Markers>,
+}
+
+impl Link {
+    fn set_state(&self, state: ConnectionState) {
+        // the data screen may already be gone
+        let _ = self.state.send(state);
+    }
+
+    fn status(&self, status: String) {
+        let _ = self.status.send(status);
     }
 }
 
@@ -40,57 +281,231 @@ pub async fn update(
     metadata: Meta,
     paths: Paths,
 ) -> Result<(), tokio::io::Error> {
+    // paths from profile templates may point into directories that don't exist yet
+    for path in [&paths.hr, &paths.acc, &paths.ecg, &paths.db, &paths.raw] {
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+        }
+    }
     init(settings, metadata, paths).await?;
     Ok(())
 }
 
+// Reasons connecting to a device can fail
+#[derive(Debug)]
+pub enum ConnectError {
+    Polar(Error),
+    TimedOut(Duration),
+    Attempts(u8),
+    Cancelled,
+    Output(tokio::io::Error),
+    Settings(String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Polar(e) => write!(f, "{}", e),
+            ConnectError::TimedOut(time) => {
+                write!(f, "Could not connect within {} seconds", time.as_secs())
+            }
+            ConnectError::Attempts(n) => write!(f, "Could not connect after {} attempts", n),
+            ConnectError::Cancelled => write!(f, "Connection cancelled"),
+            ConnectError::Output(e) => write!(f, "Unable to create output files: {}", e),
+            ConnectError::Settings(e) => write!(f, "Measurement settings rejected: {}", e),
+        }
+    }
+}
+
+impl From<Error> for ConnectError {
+    fn from(e: Error) -> Self {
+        ConnectError::Polar(e)
+    }
+}
+
+// Try to connect a limited number of times, backing off between attempts
+async fn connect(
+    sensor: &mut PolarSensor,
+    id: &str,
+    attempts: u8,
+    status: &Sender<String>,
+) -> Result<(), ConnectError> {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=attempts {
+        log::info!("{}: connection attempt {} of {}", id, attempt, attempts);
+        let _ = status.send(format!(
+            "Connecting (attempt {} of {})...",
+            attempt, attempts
+        ));
+        match sensor.connect().await {
+            Err(Error::NoBleAdaptor) => return Err(Error::NoBleAdaptor.into()),
+            Err(why) => {
+                log::warn!("{}: attempt {} failed: {}", id, attempt, why);
+                let _ = status.send(format!("Attempt {} failed: {}", attempt, why));
+            }
+            Ok(()) if sensor.is_connected().await => return Ok(()),
+            Ok(()) => log::warn!("{}: attempt {} did not connect", id, attempt),
+        }
+
+        if attempt < attempts {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    Err(ConnectError::Attempts(attempts))
+}
+
 // Create new device
+#[allow(clippy::too_many_arguments)]
 pub async fn new_device(
     id: String,
-    Setting {
-        hr,
-        ecg,
-        acc,
-        range,
-        rate,
-    }: Setting,
+    settings: Setting,
     rx: Receiver<bool>,
+    done: oneshot::Sender<Source>,
+    mut cancel: Receiver<bool>,
+    mut metadata: Meta,
     paths: Paths,
     sender: DataSender,
-) -> Result<PolarSensor, Error> {
-    let mut sensor = PolarSensor::new(id).await?;
+    clock: SessionClock,
+    markers: Arc<Markers>,
+) -> Result<(PolarSensor, Link), ConnectError> {
+    let mut sensor = PolarSensor::new(id.clone()).await?;
 
-    while !sensor.is_connected().await {
-        match sensor.connect().await {
-            Err(Error::NoBleAdaptor) => {
-                eprintln!("No bluetooth adapter found");
-                return Err(Error::NoBleAdaptor);
-            }
-            Err(why) => eprintln!("Could not connect: {:?}", why),
-            _ => {}
+    let timeout = Duration::from_secs(settings.timeout as u64);
+    let res = tokio::select! {
+        res = tokio::time::timeout(timeout, connect(&mut sensor, &id, settings.attempts, &sender.status)) => {
+            res.unwrap_or(Err(ConnectError::TimedOut(timeout)))
         }
+        _ = cancel.changed() => Err(ConnectError::Cancelled),
+    };
+    if let Err(e) = res {
+        sender.status(e.to_string());
+        return Err(e);
     }
+    sender.status("Connected".to_string());
+    sender.state(ConnectionState::Connected);
+    sender.events.info("Connected".to_string());
 
-    let _ = sensor.range(range);
-    let _ = sensor.sample_rate(rate);
+    // not every strap answers, which is no reason to give up on it
+    match read_info(&id).await {
+        Ok(info) => {
+            if info.low_battery() {
+                let msg = format!("Battery low ({}%)", info.battery.unwrap_or_default());
+                sender.status(format!("Connected, {}", msg.to_lowercase()));
+                sender.events.warn(msg);
+            }
+            sender.info(Some(info.clone()));
+            metadata.device = Some(info);
+        }
+        Err(e) => {
+            let msg = format!("Could not read device information: {}", e);
+            sender.status(format!("Connected, {}", msg.to_lowercase()));
+            sender.events.warn(msg);
+        }
+    }
 
-    if hr {
-        sensor.subscribe(NotifyStream::HeartRate).await?;
+    if let Some(pmd) = apply_settings(&mut sensor, settings, &sender).await? {
+        metadata.pmd = Some(pmd);
+        sender.pmd(Some(pmd));
     }
-    if ecg || acc {
-        sensor.subscribe(NotifyStream::MeasurementData).await?;
+
+    subscribe(&sensor, &id, settings).await?;
+
+    let gaps = Arc::new(Gaps::default());
+    let link = Link {
+        id: id.clone(),
+        running: rx.clone(),
+        settings,
+        status: Arc::clone(&sender.status),
+        state: Arc::clone(&sender.state),
+        gaps: Arc::clone(&gaps),
+        events: sender.events.clone(),
+        metadata: metadata.clone(),
+        sender: Arc::new(sender),
+        markers: Arc::clone(&markers),
+    };
+    let handler = Handler::new(
+        rx,
+        done,
+        settings,
+        metadata,
+        paths,
+        Arc::clone(&link.sender),
+        gaps,
+        clock,
+        markers,
+    );
+    if settings.format == Format::Csv {
+        handler.write_sensor_info().await;
     }
+    sensor.event_handler(handler);
 
-    if ecg {
-        sensor.data_type_push(H10MeasurementType::Ecg)
+    Ok((sensor, link))
+}
+
+// Select ECG/acceleration and set range and sample rate, checking them against
+// what the sensor reports it supports. Returns the settings it was started with.
+async fn apply_settings(
+    sensor: &mut PolarSensor,
+    settings: Setting,
+    sender: &DataSender,
+) -> Result<Option<PmdSettings>, ConnectError> {
+    // range and rate can only be set once acceleration is selected
+    if settings.ecg {
+        sensor.data_type_push(H10MeasurementType::Ecg);
     }
-    if acc {
+    if settings.acc {
         sensor.data_type_push(H10MeasurementType::Acc);
+        sensor.range(settings.range).map_err(|e| {
+            ConnectError::Settings(format!("acceleration range {}: {}", settings.range, e))
+        })?;
+        sensor.sample_rate(settings.rate).map_err(|e| {
+            ConnectError::Settings(format!("acceleration sample rate {}: {}", settings.rate, e))
+        })?;
+    }
+    if !(settings.ecg || settings.acc) {
+        return Ok(None);
     }
 
-    sensor.event_handler(Handler::new(rx, rate, paths, sender));
+    let mut pmd = PmdSettings::new(settings);
+    // settings come back in the same order as the selected data types
+    let types = sensor.data_type().clone().unwrap_or_default();
+    match sensor.settings().await {
+        Ok(supported) => {
+            for (ty, stream) in types.iter().zip(&supported) {
+                let checked = match ty {
+                    H10MeasurementType::Ecg => pmd.ecg_rate.map_or(Ok(()), |rate| {
+                        check_supported("ECG sample rate", rate, stream.sample_rate())
+                    }),
+                    H10MeasurementType::Acc => check_supported(
+                        "Acceleration range",
+                        settings.range,
+                        stream.range().as_deref().unwrap_or_default(),
+                    )
+                    .and_then(|_| {
+                        check_supported(
+                            "Acceleration sample rate",
+                            settings.rate,
+                            stream.sample_rate(),
+                        )
+                    }),
+                };
+                checked.map_err(ConnectError::Settings)?;
+            }
+            pmd.verified = true;
+        }
+        Err(e) => {
+            let msg = format!("Could not check supported measurement settings: {}", e);
+            sender.status(format!("Connected, {}", msg.to_lowercase()));
+            sender.events.warn(msg);
+        }
+    }
 
-    Ok(sensor)
+    Ok(Some(pmd))
 }
 
 // Reset sensor for future use
@@ -98,10 +513,11 @@ pub async fn reset(manager: Arc<Mutex<SensorManager>>) -> Result<(), Error> {
     let mut unlocked = manager.lock().await;
     let sensor = unlocked.sensor.as_mut().ok_or(Error::NoDevice)?;
 
-    let _ = sensor.range(8);
-    let _ = sensor.sample_rate(200);
-
-    let tys = sensor.data_type().as_ref().expect("Unreachable").clone();
+    let tys = sensor.data_type().clone().unwrap_or_default();
+    if tys.contains(&H10MeasurementType::Acc) {
+        sensor.range(8)?;
+        sensor.sample_rate(200)?;
+    }
     for t in tys {
         sensor.data_type_pop(t);
     }
@@ -112,22 +528,264 @@ pub async fn reset(manager: Arc<Mutex<SensorManager>>) -> Result<(), Error> {
 // Handle bluetooth events
 struct Handler {
     rx: Receiver<bool>,
+    // where the recording ended up, sent once it is complete
+    done: sync::Mutex<Option<oneshot::Sender<Source>>>,
     rate: u8,
+    format: Format,
+    timing: Timing,
     paths: Paths,
-    sender: DataSender,
-    hr_start: sync::Mutex<Option<u64>>,
+    csv: CsvWriter,
+    sinks: Arc<sync::Mutex<Sinks>>,
+    db: Arc<sync::Mutex<Database>>,
+    raw: Option<RawLog>,
+    sender: Arc<DataSender>,
+    gaps: Arc<Gaps>,
+    clock: SessionClock,
+    // sensor time that lines up with the start of the session clock
     pmd_start: sync::Mutex<Option<u64>>,
+    loss: sync::Mutex<Loss>,
+    loss_warnings: sync::Mutex<LossWarnings>,
+    settings: Setting,
+    // when the first data arrived, for the recorded duration
+    started: sync::Mutex<Option<Instant>>,
+    markers: Arc<Markers>,
 }
 
 impl Handler {
-    fn new(rx: Receiver<bool>, rate: u8, paths: Paths, sender: DataSender) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        rx: Receiver<bool>,
+        done: oneshot::Sender<Source>,
+        settings: Setting,
+        metadata: Meta,
+        paths: Paths,
+        sender: Arc<DataSender>,
+        gaps: Arc<Gaps>,
+        clock: SessionClock,
+        markers: Arc<Markers>,
+    ) -> Self {
+        // the graph starts over with every trial
+        sender.clear_ecg();
         Self {
             rx,
-            rate,
+            done: sync::Mutex::new(Some(done)),
+            rate: settings.rate,
+            format: settings.format,
+            timing: settings.timing,
+            csv: CsvWriter::new(&metadata, settings),
+            sinks: Arc::new(sync::Mutex::new(Sinks::new(
+                metadata.clone(),
+                paths.clone(),
+            ))),
+            db: Arc::new(sync::Mutex::new(Database::new(metadata, paths.db.clone()))),
+            raw: settings.raw.then(|| RawLog::new(paths.raw.clone())),
             paths,
             sender,
-            hr_start: sync::Mutex::new(None),
+            gaps,
+            clock,
             pmd_start: sync::Mutex::new(None),
+            loss: sync::Mutex::new(Loss::default()),
+            loss_warnings: sync::Mutex::new(LossWarnings::default()),
+            settings,
+            started: sync::Mutex::new(None),
+            markers,
+        }
+    }
+}
+
+impl Handler {
+    // Streams being recorded with the file each one goes to
+    fn streams(&self) -> impl Iterator<Item = (MeasureType, &str)> {
+        [
+            (self.settings.hr, MeasureType::Hr, &self.paths.hr),
+            (self.settings.ecg, MeasureType::Ecg, &self.paths.ecg),
+            (self.settings.acc, MeasureType::Acc, &self.paths.acc),
+        ]
+        .into_iter()
+        .filter(|stream| stream.0)
+        .map(|(_, ty, path)| (ty, path.as_str()))
+    }
+
+    // Csv files were created before connecting, so sensor information goes in afterwards
+    async fn write_sensor_info(&self) {
+        for (ty, path) in self.streams() {
+            if let Err(e) = self.csv.sensor_info(ty, path).await {
+                self.sender
+                    .events
+                    .error(format!("Sensor information writing error: {}", e));
+            }
+        }
+    }
+
+    // Each strap has its own clock, so map its first frame onto the session clock
+    fn align_pmd(&self, sensor_time: u64) {
+        let mut start = self.pmd_start.lock().expect("stupid mutex");
+        if start.is_none() {
+            let elapsed = hr_timestamp(&self.clock);
+            *start = Some(sensor_time.saturating_sub(elapsed));
+        }
+    }
+
+    // Count samples the sensor sent but never arrived, returns how many went missing before
+    // this frame and the time of the frame before, if it directly precedes this one
+    fn check_loss(
+        &self,
+        ty: MeasureType,
+        time_stamp: u64,
+        samples: usize,
+        period: u64,
+    ) -> (u64, Option<u64>) {
+        let mut loss = self.loss.lock().expect("stupid mutex");
+        let previous = loss.last(ty);
+        let missing = loss.frame(ty, time_stamp, samples as u64, period);
+        self.sender.loss(*loss);
+        let warning =
+            self.loss_warnings
+                .lock()
+                .expect("stupid mutex")
+                .frame(ty, missing, Instant::now());
+        match warning {
+            Some(LossWarning::Started(missing)) => self.sender.events.warn(format!(
+                "{:?} frames are being lost, {} samples missing",
+                ty, missing
+            )),
+            Some(LossWarning::Summary(missing)) => self.sender.events.warn(format!(
+                "{} more {:?} samples missing, {} lost so far",
+                missing,
+                ty,
+                match ty {
+                    MeasureType::Acc => loss.acc,
+                    _ => loss.ecg,
+                }
+            )),
+            None => {}
+        }
+        (missing, previous.filter(|_| missing == 0))
+    }
+
+    fn mark_started(&self) {
+        self.started
+            .lock()
+            .expect("stupid mutex")
+            .get_or_insert_with(Instant::now);
+    }
+
+    // Write event markers added since the last data arrived
+    async fn write_markers(&self) {
+        let markers = self.markers.take();
+        if markers.is_empty() {
+            return;
+        }
+        let res = match self.format {
+            Format::Csv => {
+                let mut res = Ok(());
+                for (ty, path) in self.streams() {
+                    for (time, label) in &markers {
+                        res = res.and(self.csv.marker(ty, path, *time, label).await);
+                    }
+                }
+                res
+            }
+            Format::Parquet => {
+                self.sinks.lock().expect("stupid mutex").mark(markers);
+                Ok(())
+            }
+            Format::Sqlite => sqlite::insert_markers(&self.db, markers).await,
+        };
+        if let Err(e) = res {
+            self.sender
+                .events
+                .error(format!("Marker writing error: {}", e));
+        }
+    }
+
+    // Files or database trial the recording went to, None when nothing was stored
+    async fn source(&self) -> Option<Source> {
+        match self.format {
+            Format::Csv => {
+                let mut files = vec![];
+                for (ty, path) in self.streams() {
+                    let segments = self.csv.segments(ty, path).await;
+                    files.extend(segments.into_iter().map(PathBuf::from));
+                }
+                Some(Source::Files(files))
+            }
+            Format::Parquet => Some(Source::Files(
+                self.streams()
+                    .map(|(_, path)| PathBuf::from(path))
+                    .collect(),
+            )),
+            Format::Sqlite => {
+                let trial = self.db.lock().expect("stupid mutex").trial()?;
+                Some(Source::Database(PathBuf::from(&self.paths.db), trial))
+            }
+        }
+    }
+
+    // Store how long data was recorded for, once measurement stops
+    async fn write_duration(&self) {
+        let started = self.started.lock().expect("stupid mutex").take();
+        let secs = match started {
+            Some(started) => started.elapsed().as_secs_f64(),
+            None => return,
+        };
+        let res = match self.format {
+            Format::Csv => {
+                let mut res = Ok(());
+                for (ty, path) in self.streams() {
+                    res = res.and(self.csv.duration(ty, path, secs).await);
+                }
+                res
+            }
+            Format::Parquet => {
+                self.sinks.lock().expect("stupid mutex").set_duration(secs);
+                Ok(())
+            }
+            Format::Sqlite => sqlite::record_duration(&self.db, secs).await,
+        };
+        match res {
+            Ok(()) => self
+                .sender
+                .events
+                .info(format!("Recorded for {:.1} s", secs)),
+            Err(e) => self
+                .sender
+                .events
+                .error(format!("Duration writing error: {}", e)),
+        }
+    }
+
+    // Mark where data is missing before writing the first data after a reconnect
+    // or after frames were dropped
+    async fn check_gap(&self, ty: MeasureType, missing: u64) {
+        if !self.gaps.take(ty) && missing == 0 {
+            return;
+        }
+
+        let res = match self.format {
+            Format::Csv => {
+                let path = match ty {
+                    MeasureType::Hr => &self.paths.hr,
+                    MeasureType::Ecg => &self.paths.ecg,
+                    MeasureType::Acc => &self.paths.acc,
+                };
+                self.csv.gap(ty, path, missing).await
+            }
+            Format::Parquet => self
+                .sinks
+                .lock()
+                .expect("stupid mutex")
+                .mark_gap(ty)
+                .map_err(tokio::io::Error::other),
+            Format::Sqlite => {
+                self.db.lock().expect("stupid mutex").mark_gap(ty);
+                Ok(())
+            }
+        };
+        if let Err(e) = res {
+            self.sender
+                .events
+                .error(format!("Gap marker writing error: {}", e));
         }
     }
 }
@@ -135,61 +793,209 @@ impl Handler {
 #[async_trait]
 impl EventHandler for Handler {
     async fn heart_rate_update(&self, _ctx: &PolarSensor, heartrate: HeartRate) {
-        match write_hr(heartrate, &self.paths.hr, &self.hr_start).await {
+        self.mark_started();
+        self.write_markers().await;
+        self.check_gap(MeasureType::Hr, 0).await;
+        let res = match self.format {
+            Format::Csv => write_hr(heartrate, &self.paths.hr, &self.clock, &self.csv).await,
+            Format::Parquet => parquet::write_hr(heartrate, &self.sinks, &self.clock).await,
+            Format::Sqlite => sqlite::write_hr(heartrate, &self.db, &self.clock).await,
+        };
+        match res {
             Ok(last) => {
                 self.sender.hr(last.0);
                 self.sender.rr(last.1);
             }
-            Err(e) => eprintln!("HR writing error: {:?}", e),
+            Err(e) => self.sender.events.error(format!("HR writing error: {}", e)),
         }
     }
 
     async fn measurement_update(&self, _ctx: &PolarSensor, data: PmdRead) {
-        match write_data(data, self.rate, &self.paths, &self.pmd_start).await {
+        self.mark_started();
+        self.write_markers().await;
+        let ty = match data.data_type() {
+            H10MeasurementType::Ecg => MeasureType::Ecg,
+            H10MeasurementType::Acc => MeasureType::Acc,
+        };
+        let time_stamp = data.time_stamp();
+        let period = sample_period(data.data_type(), self.rate);
+        let samples = frame_samples(data);
+        let (missing, previous) = self.check_loss(ty, time_stamp, samples.len(), period);
+        let (first, spacing) =
+            frame_timing(self.timing, time_stamp, previous, samples.len(), period);
+        self.align_pmd(first);
+        let samples = timestamp_samples(first, spacing, samples, &self.pmd_start);
+        if matches!(ty, MeasureType::Ecg) {
+            self.sender.ecg(&samples);
+        }
+        self.check_gap(ty, missing).await;
+        let res = match self.format {
+            Format::Csv => write_data(ty, samples, &self.paths, &self.csv).await,
+            Format::Parquet => parquet::write_data(samples, &self.sinks).await,
+            Format::Sqlite => sqlite::write_data(samples, &self.db).await,
+        };
+        match res {
             Ok(Some(last)) => {
                 self.sender.acc(last);
             }
-            Err(e) => eprintln!("Measurement writing error: {:?}", e),
+            Err(e) => self
+                .sender
+                .events
+                .error(format!("Measurement writing error: {}", e)),
             _ => {}
         }
     }
 
+    // Log notifications as received, before arctic parses them
+    async fn raw_update(&self, _ctx: &PolarSensor, stream: NotifyStream, value: &[u8]) {
+        let raw = match &self.raw {
+            Some(raw) => raw,
+            None => return,
+        };
+        let kind = match stream {
+            NotifyStream::HeartRate => FrameKind::HeartRate,
+            NotifyStream::MeasurementData => FrameKind::Pmd,
+            _ => return,
+        };
+        let mut res = Ok(());
+        if self.gaps.take_raw() {
+            res = raw.write(FrameKind::Gap, &[]);
+        }
+        if let Err(e) = res.and_then(|_| raw.write(kind, value)) {
+            self.sender
+                .events
+                .error(format!("Raw frame writing error: {}", e));
+        }
+    }
+
     async fn should_continue(&self) -> bool {
-        *self.rx.borrow()
+        let cont = *self.rx.borrow();
+        if !cont {
+            self.write_markers().await;
+            self.write_duration().await;
+            if let Err(e) = self.csv.finish().await {
+                self.sender
+                    .events
+                    .error(format!("Error closing csv files: {}", e));
+            }
+            // parquet files need their footer written once measurement stops
+            if let Err(e) = parquet::close(&self.sinks).await {
+                self.sender
+                    .events
+                    .error(format!("Error closing parquet files: {}", e));
+            }
+            if let Err(e) = sqlite::close(&self.db).await {
+                self.sender
+                    .events
+                    .error(format!("Error closing database: {}", e));
+            }
+            let source = self.source().await;
+            // nobody waits for it if no report is written
+            if let (Some(source), Some(done)) =
+                (source, self.done.lock().expect("stupid mutex").take())
+            {
+                let _ = done.send(source);
+            }
+        }
+        cont
     }
 }
 
+// Samples kept for the graph, several seconds at the ECG rate
+const RECENT_ECG: usize = 1000;
+
 pub struct DataSender {
     hr: Sender<u8>,
     rr: Sender<String>,
     acc: Sender<(i16, i16, i16)>,
+    ecg: Sender<VecDeque<(u64, i32)>>,
+    status: Arc<Sender<String>>,
+    state: Arc<Sender<ConnectionState>>,
+    info: Sender<Option<DeviceInfo>>,
+    pmd: Sender<Option<PmdSettings>>,
+    loss: Sender<Loss>,
+    events: Events,
 }
 
 impl DataSender {
-    pub fn init_transmitters() -> (Self, DataReceiver) {
+    pub fn init_transmitters(events: Events) -> (Self, DataReceiver) {
         let (hr_tx, hr_rx) = channel(0);
         let (rr_tx, rr_rx) = channel("".to_string());
         let (acc_tx, acc_rx) = channel((0, 0, 0));
+        let (ecg_tx, ecg_rx) = channel(VecDeque::new());
+        let (status_tx, status_rx) = channel("".to_string());
+        let (state_tx, state_rx) = channel(ConnectionState::default());
+        let (info_tx, info_rx) = channel(None);
+        let (pmd_tx, pmd_rx) = channel(None);
+        let (loss_tx, loss_rx) = channel(Loss::default());
 
         (
             Self {
                 hr: hr_tx,
                 rr: rr_tx,
                 acc: acc_tx,
+                ecg: ecg_tx,
+                status: Arc::new(status_tx),
+                state: Arc::new(state_tx),
+                info: info_tx,
+                pmd: pmd_tx,
+                loss: loss_tx,
+                events,
             },
-            DataReceiver::new(hr_rx, rr_rx, acc_rx),
+            DataReceiver::new(
+                hr_rx, rr_rx, acc_rx, ecg_rx, status_rx, state_rx, info_rx, pmd_rx, loss_rx,
+            ),
         )
     }
 
+    // Nobody is listening once the data screen is closed, which is fine
     pub fn hr(&self, hr: u8) {
-        self.hr.send(hr).expect("hr sender failed");
+        let _ = self.hr.send(hr);
     }
 
     pub fn rr(&self, rr: String) {
-        self.rr.send(rr).expect("ecg sender failed");
+        let _ = self.rr.send(rr);
     }
 
     pub fn acc(&self, acc: (i16, i16, i16)) {
-        self.acc.send(acc).expect("acc sender failed");
+        let _ = self.acc.send(acc);
+    }
+
+    // Keep the newest ecg samples for the graph, which takes the ones it hasn't seen yet
+    pub fn ecg(&self, samples: &[(u64, Sample)]) {
+        self.ecg.send_modify(|recent| {
+            for (time, sample) in samples {
+                if let Sample::Ecg(val) = sample {
+                    recent.push_back((*time, *val));
+                }
+            }
+            while recent.len() > RECENT_ECG {
+                recent.pop_front();
+            }
+        });
+    }
+
+    pub fn clear_ecg(&self) {
+        self.ecg.send_replace(VecDeque::new());
+    }
+
+    pub fn status(&self, status: String) {
+        let _ = self.status.send(status);
+    }
+
+    pub fn state(&self, state: ConnectionState) {
+        let _ = self.state.send(state);
+    }
+
+    pub fn info(&self, info: Option<DeviceInfo>) {
+        let _ = self.info.send(info);
+    }
+
+    pub fn pmd(&self, pmd: Option<PmdSettings>) {
+        let _ = self.pmd.send(pmd);
+    }
+
+    pub fn loss(&self, loss: Loss) {
+        let _ = self.loss.send(loss);
     }
 }
@@ -0,0 +1,519 @@
+use super::fs::{hr_timestamp, MeasureType, Sample};
+use crate::menu::{Meta, Paths};
+use arctic::HeartRate;
+use parquet::{
+    basic::Compression,
+    data_type::{Int32Type, Int64Type},
+    errors::ParquetError,
+    file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+use tokio::io::Error;
+
+// Rows to buffer before they are written out as a row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+const HR_SCHEMA: &str = "
+message hr {
+    required int64 time;
+    required int32 bpm (INTEGER(8, false));
+    required group rr (LIST) {
+        repeated group list {
+            required int32 element (INTEGER(16, false));
+        }
+    }
+}";
+
+const ECG_SCHEMA: &str = "
+message ecg {
+    required int64 time;
+    required int32 val;
+}";
+
+const ACC_SCHEMA: &str = "
+message acc {
+    required int64 time;
+    required int32 x (INTEGER(16, true));
+    required int32 y (INTEGER(16, true));
+    required int32 z (INTEGER(16, true));
+}";
+
+// Buffered columns waiting to be written
+enum Columns {
+    Hr {
+        time: Vec<i64>,
+        bpm: Vec<i32>,
+        rr: Vec<Vec<u16>>,
+    },
+    Ecg {
+        time: Vec<i64>,
+        val: Vec<i32>,
+    },
+    Acc {
+        time: Vec<i64>,
+        x: Vec<i32>,
+        y: Vec<i32>,
+        z: Vec<i32>,
+    },
+}
+
+impl Columns {
+    fn new(ty: &MeasureType) -> Self {
+        match ty {
+            MeasureType::Hr => Columns::Hr {
+                time: vec![],
+                bpm: vec![],
+                rr: vec![],
+            },
+            MeasureType::Ecg => Columns::Ecg {
+                time: vec![],
+                val: vec![],
+            },
+            MeasureType::Acc => Columns::Acc {
+                time: vec![],
+                x: vec![],
+                y: vec![],
+                z: vec![],
+            },
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Columns::Hr { time, .. } | Columns::Ecg { time, .. } | Columns::Acc { time, .. } => {
+                time.len()
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Columns::Hr { time, bpm, rr } => {
+                time.clear();
+                bpm.clear();
+                rr.clear();
+            }
+            Columns::Ecg { time, val } => {
+                time.clear();
+                val.clear();
+            }
+            Columns::Acc { time, x, y, z } => {
+                time.clear();
+                x.clear();
+                y.clear();
+                z.clear();
+            }
+        }
+    }
+}
+
+// Writes one measurement type to a parquet file
+pub struct ParquetSink {
+    writer: Option<SerializedFileWriter<File>>,
+    columns: Columns,
+    // time of the first row after each reconnect
+    gaps: Vec<i64>,
+    pending_gap: bool,
+    // seconds of recording, known once measurement stops
+    duration: Option<f64>,
+    markers: Vec<(u64, String)>,
+}
+
+impl ParquetSink {
+    pub fn create(ty: MeasureType, path: &str, metadata: &Meta) -> Result<Self, ParquetError> {
+        let schema = match ty {
+            MeasureType::Hr => HR_SCHEMA,
+            MeasureType::Ecg => ECG_SCHEMA,
+            MeasureType::Acc => ACC_SCHEMA,
+        };
+        let schema = Arc::new(parse_message_type(schema)?);
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_key_value_metadata(Some(key_values(metadata)))
+            .build();
+        let file = File::create(path)?;
+
+        Ok(Self {
+            writer: Some(SerializedFileWriter::new(file, schema, Arc::new(props))?),
+            columns: Columns::new(&ty),
+            gaps: vec![],
+            pending_gap: false,
+            duration: None,
+            markers: vec![],
+        })
+    }
+
+    // The next row pushed is the first one after a reconnect
+    pub fn mark_gap(&mut self) {
+        self.pending_gap = true;
+    }
+
+    fn check_gap(&mut self, timestamp: u64) {
+        if self.pending_gap {
+            self.pending_gap = false;
+            self.gaps.push(timestamp as i64);
+        }
+    }
+
+    pub fn push_hr(&mut self, timestamp: u64, bpm: u8, rr: Vec<u16>) -> Result<(), ParquetError> {
+        self.check_gap(timestamp);
+        if let Columns::Hr {
+            time,
+            bpm: bpms,
+            rr: rrs,
+        } = &mut self.columns
+        {
+            time.push(timestamp as i64);
+            bpms.push(bpm as i32);
+            rrs.push(rr);
+        }
+        self.maybe_flush()
+    }
+
+    pub fn push_sample(&mut self, timestamp: u64, sample: Sample) -> Result<(), ParquetError> {
+        self.check_gap(timestamp);
+        match (&mut self.columns, sample) {
+            (Columns::Ecg { time, val }, Sample::Ecg(v)) => {
+                time.push(timestamp as i64);
+                val.push(v);
+            }
+            (Columns::Acc { time, x, y, z }, Sample::Acc(sx, sy, sz)) => {
+                time.push(timestamp as i64);
+                x.push(sx as i32);
+                y.push(sy as i32);
+                z.push(sz as i32);
+            }
+            _ => {}
+        }
+        self.maybe_flush()
+    }
+
+    fn maybe_flush(&mut self) -> Result<(), ParquetError> {
+        if self.columns.len() >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write all buffered rows as a row group
+    fn flush(&mut self) -> Result<(), ParquetError> {
+        let writer = match &mut self.writer {
+            Some(writer) if self.columns.len() > 0 => writer,
+            _ => return Ok(()),
+        };
+        let mut group = writer.next_row_group()?;
+
+        match &self.columns {
+            Columns::Hr { time, bpm, rr } => {
+                write_i64(&mut group, time)?;
+                write_i32(&mut group, bpm)?;
+
+                // one definition/repetition level per list element, or one for an empty list
+                let mut values = vec![];
+                let mut def = vec![];
+                let mut rep = vec![];
+                for row in rr {
+                    if row.is_empty() {
+                        def.push(0);
+                        rep.push(0);
+                    }
+                    for (i, v) in row.iter().enumerate() {
+                        values.push(*v as i32);
+                        def.push(1);
+                        rep.push(if i == 0 { 0 } else { 1 });
+                    }
+                }
+                if let Some(mut col) = group.next_column()? {
+                    col.typed::<Int32Type>()
+                        .write_batch(&values, Some(&def), Some(&rep))?;
+                    col.close()?;
+                }
+            }
+            Columns::Ecg { time, val } => {
+                write_i64(&mut group, time)?;
+                write_i32(&mut group, val)?;
+            }
+            Columns::Acc { time, x, y, z } => {
+                write_i64(&mut group, time)?;
+                write_i32(&mut group, x)?;
+                write_i32(&mut group, y)?;
+                write_i32(&mut group, z)?;
+            }
+        }
+
+        group.close()?;
+        self.columns.clear();
+
+        Ok(())
+    }
+
+    // Write remaining rows and the file footer
+    pub fn close(&mut self) -> Result<(), ParquetError> {
+        self.flush()?;
+        if let Some(mut writer) = self.writer.take() {
+            if !self.gaps.is_empty() {
+                let gaps: Vec<String> = self.gaps.iter().map(i64::to_string).collect();
+                writer.append_key_value_metadata(KeyValue::new("gaps".to_string(), gaps.join(",")));
+            }
+            if !self.markers.is_empty() {
+                let markers: Vec<String> = self
+                    .markers
+                    .iter()
+                    .map(|(time, label)| format!("{}={}", time, label))
+                    .collect();
+                writer.append_key_value_metadata(KeyValue::new(
+                    "markers".to_string(),
+                    markers.join(","),
+                ));
+            }
+            if let Some(duration) = self.duration {
+                writer.append_key_value_metadata(KeyValue::new(
+                    "duration".to_string(),
+                    format!("{:.3}", duration),
+                ));
+            }
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("Error closing parquet file: {}", e);
+        }
+    }
+}
+
+fn write_i64(
+    group: &mut parquet::file::writer::SerializedRowGroupWriter<'_, File>,
+    values: &[i64],
+) -> Result<(), ParquetError> {
+    if let Some(mut col) = group.next_column()? {
+        col.typed::<Int64Type>().write_batch(values, None, None)?;
+        col.close()?;
+    }
+    Ok(())
+}
+
+fn write_i32(
+    group: &mut parquet::file::writer::SerializedRowGroupWriter<'_, File>,
+    values: &[i32],
+) -> Result<(), ParquetError> {
+    if let Some(mut col) = group.next_column()? {
+        col.typed::<Int32Type>().write_batch(values, None, None)?;
+        col.close()?;
+    }
+    Ok(())
+}
+
+// Store metadata in the parquet footer instead of a header row
+fn key_values(metadata: &Meta) -> Vec<KeyValue> {
+    let mut kv = vec![
+        KeyValue::new("id".to_string(), metadata.id.clone()),
+        KeyValue::new("session".to_string(), metadata.session.clone()),
+        KeyValue::new("trial".to_string(), metadata.trial.clone()),
+        KeyValue::new("date".to_string(), metadata.date.to_string()),
+        KeyValue::new("description".to_string(), metadata.description.clone()),
+        KeyValue::new("range".to_string(), metadata.settings.range.to_string()),
+        KeyValue::new("rate".to_string(), metadata.settings.rate.to_string()),
+        KeyValue::new(
+            "timing".to_string(),
+            metadata.settings.timing.key().to_string(),
+        ),
+    ];
+    kv.extend(
+        metadata
+            .fields
+            .iter()
+            .map(|(name, value)| KeyValue::new(format!("meta.{}", name), value.clone())),
+    );
+    if let Some(ecg_rate) = metadata.pmd.and_then(|p| p.ecg_rate) {
+        kv.push(KeyValue::new("ecg_rate".to_string(), ecg_rate.to_string()));
+    }
+    if let Some(device) = &metadata.device {
+        let text = |s: &Option<String>| s.clone().unwrap_or_default();
+        kv.extend([
+            KeyValue::new("device".to_string(), device.id.clone()),
+            KeyValue::new(
+                "battery".to_string(),
+                device.battery.map(|b| b.to_string()).unwrap_or_default(),
+            ),
+            KeyValue::new("manufacturer".to_string(), text(&device.manufacturer)),
+            KeyValue::new("model".to_string(), text(&device.model)),
+            KeyValue::new("firmware".to_string(), text(&device.firmware)),
+            KeyValue::new("serial".to_string(), text(&device.serial)),
+        ]);
+    }
+    kv
+}
+
+// Open parquet files lazily so they are only created once data arrives
+pub struct Sinks {
+    metadata: Meta,
+    paths: Paths,
+    hr: Option<ParquetSink>,
+    ecg: Option<ParquetSink>,
+    acc: Option<ParquetSink>,
+    // every file gets all markers once it is closed
+    markers: Vec<(u64, String)>,
+}
+
+impl Sinks {
+    pub fn new(metadata: Meta, paths: Paths) -> Self {
+        Self {
+            metadata,
+            paths,
+            hr: None,
+            ecg: None,
+            acc: None,
+            markers: vec![],
+        }
+    }
+
+    fn sink(&mut self, ty: MeasureType) -> Result<&mut ParquetSink, ParquetError> {
+        let (sink, path) = match ty {
+            MeasureType::Hr => (&mut self.hr, &self.paths.hr),
+            MeasureType::Ecg => (&mut self.ecg, &self.paths.ecg),
+            MeasureType::Acc => (&mut self.acc, &self.paths.acc),
+        };
+        if sink.is_none() {
+            *sink = Some(ParquetSink::create(ty, path, &self.metadata)?);
+        }
+        Ok(sink.as_mut().expect("sink was just created"))
+    }
+
+    // Mark a gap in a stream, opening its file if needed
+    pub fn mark_gap(&mut self, ty: MeasureType) -> Result<(), ParquetError> {
+        self.sink(ty)?.mark_gap();
+        Ok(())
+    }
+
+    pub fn mark(&mut self, markers: Vec<(u64, String)>) {
+        self.markers.extend(markers);
+    }
+
+    // Only files that were opened get the duration
+    pub fn set_duration(&mut self, secs: f64) {
+        for sink in [&mut self.hr, &mut self.ecg, &mut self.acc]
+            .into_iter()
+            .flatten()
+        {
+            sink.duration = Some(secs);
+        }
+    }
+
+    pub fn close(&mut self) -> Result<(), ParquetError> {
+        for sink in [&mut self.hr, &mut self.ecg, &mut self.acc] {
+            if let Some(mut sink) = sink.take() {
+                sink.markers = self.markers.clone();
+                sink.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Encoding and file writes block, so they run off the async workers
+async fn with_sinks<T: Send + 'static>(
+    sinks: &Arc<Mutex<Sinks>>,
+    f: impl FnOnce(&mut Sinks) -> Result<T, ParquetError> + Send + 'static,
+) -> Result<T, Error> {
+    let sinks = Arc::clone(sinks);
+    tokio::task::spawn_blocking(move || f(&mut sinks.lock().expect("stupid mutex")))
+        .await
+        .map_err(Error::other)?
+        .map_err(Error::other)
+}
+
+// Write hr data, return last data for sending
+pub async fn write_hr(
+    data: HeartRate,
+    sinks: &Arc<Mutex<Sinks>>,
+    start: &Mutex<Option<u64>>,
+) -> Result<(u8, String), Error> {
+    let timestamp = hr_timestamp(start);
+    let bpm = *data.bpm();
+    let rr = data.rr().clone().unwrap_or_default();
+    let rr_text: String = rr.iter().map(|i| format!(",{}", i)).collect();
+
+    with_sinks(sinks, move |sinks| {
+        sinks.sink(MeasureType::Hr)?.push_hr(timestamp, bpm, rr)
+    })
+    .await?;
+
+    Ok((bpm, rr_text))
+}
+
+// Write ecg/acc data, return last acceleration for sending
+pub async fn write_data(
+    samples: Vec<(u64, Sample)>,
+    sinks: &Arc<Mutex<Sinks>>,
+) -> Result<Option<(i16, i16, i16)>, Error> {
+    let last = samples.iter().rev().find_map(|(_, sample)| match sample {
+        Sample::Acc(x, y, z) => Some((*x, *y, *z)),
+        Sample::Ecg(_) => None,
+    });
+    with_sinks(sinks, move |sinks| {
+        for (timestamp, sample) in samples {
+            let ty = match sample {
+                Sample::Ecg(_) => MeasureType::Ecg,
+                Sample::Acc(..) => MeasureType::Acc,
+            };
+            sinks.sink(ty)?.push_sample(timestamp, sample)?;
+        }
+        Ok(())
+    })
+    .await?;
+
+    Ok(last)
+}
+
+// Write the footers of every open file
+pub async fn close(sinks: &Arc<Mutex<Sinks>>) -> Result<(), Error> {
+    with_sinks(sinks, Sinks::close).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn write_and_read_hr() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hr.parquet");
+        let path = path.to_str().unwrap();
+
+        let mut sink = ParquetSink::create(MeasureType::Hr, path, &Meta::default()).unwrap();
+        sink.push_hr(0, 60, vec![1104, 793]).unwrap();
+        sink.mark_gap();
+        sink.push_hr(1000, 61, vec![]).unwrap();
+        sink.close().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        let meta = reader.metadata().file_metadata();
+        assert_eq!(meta.num_rows(), 2);
+        assert!(meta
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .any(|kv| kv.key == "rate" && kv.value.as_deref() == Some("200")));
+        assert!(meta
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .any(|kv| kv.key == "gaps" && kv.value.as_deref() == Some("1000")));
+
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|r| r.unwrap().to_string())
+            .collect();
+        assert_eq!(rows[0], "{time: 0, bpm: 60, rr: [1104, 793]}");
+        assert_eq!(rows[1], "{time: 1000, bpm: 61, rr: []}");
+    }
+}
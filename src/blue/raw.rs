@@ -0,0 +1,141 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Magic bytes at the start of every raw log
+const MAGIC: &[u8; 8] = b"PARAW\x00\x00\x01";
+
+// Which notification a frame came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    HeartRate = 0,
+    Pmd = 1,
+    // the connection dropped before this point, no bytes
+    Gap = 2,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = Error;
+
+    fn try_from(byte: u8) -> Result<Self, Error> {
+        match byte {
+            0 => Ok(FrameKind::HeartRate),
+            1 => Ok(FrameKind::Pmd),
+            2 => Ok(FrameKind::Gap),
+            _ => Err(Error::new(ErrorKind::InvalidData, "unknown frame kind")),
+        }
+    }
+}
+
+// A single notification, with its bytes as the strap sent them and the time
+// (ns since the unix epoch) it was received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub received: u64,
+    pub bytes: Vec<u8>,
+}
+
+// Create/Truncate a raw log
+pub fn init(path: &str) -> Result<(), Error> {
+    let mut file = File::create(path)?;
+    file.write_all(MAGIC)?;
+    Ok(())
+}
+
+// Appends length-prefixed frames to a raw log
+pub struct RawLog {
+    path: String,
+    writer: Mutex<Option<BufWriter<File>>>,
+}
+
+impl RawLog {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            writer: Mutex::new(None),
+        }
+    }
+
+    pub fn write(&self, kind: FrameKind, bytes: &[u8]) -> Result<(), Error> {
+        let received = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards????????")
+            .as_nanos() as u64;
+
+        let mut writer = self.writer.lock().expect("stupid mutex");
+        if writer.is_none() {
+            let file = OpenOptions::new().append(true).open(&self.path)?;
+            *writer = Some(BufWriter::new(file));
+        }
+        let writer = writer.as_mut().expect("writer was just opened");
+
+        writer.write_all(&[kind as u8])?;
+        writer.write_all(&received.to_le_bytes())?;
+        writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        writer.write_all(bytes)?;
+        writer.flush()
+    }
+}
+
+// Read every frame back from a raw log
+pub fn read_frames(path: &str) -> Result<Vec<Frame>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a raw frame log"));
+    }
+
+    let mut frames = vec![];
+    let mut kind = [0u8; 1];
+    // a missing kind byte is the end of the file, anything else missing is truncation
+    while reader.read(&mut kind)? == 1 {
+        let mut received = [0u8; 8];
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut received)?;
+        reader.read_exact(&mut len)?;
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut bytes)?;
+
+        frames.push(Frame {
+            kind: FrameKind::try_from(kind[0])?,
+            received: u64::from_le_bytes(received),
+            bytes,
+        });
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arctic::HeartRate;
+
+    const ACC: [u8; 22] = [
+        0x02, 0xea, 0x54, 0xa2, 0x42, 0x8b, 0x45, 0x52, 0x08, 0x01, 0x45, 0xff, 0xe4, 0xff, 0xb5,
+        0x03, 0x45, 0xff, 0xe4, 0xff, 0xb8, 0x03,
+    ];
+
+    #[test]
+    fn write_and_read_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frames.raw");
+        let path = path.to_str().unwrap();
+
+        init(path).unwrap();
+        let log = RawLog::new(path.to_string());
+        log.write(FrameKind::Pmd, &ACC).unwrap();
+        log.write(FrameKind::HeartRate, &[0x00, 61]).unwrap();
+
+        let frames = read_frames(path).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].kind, FrameKind::Pmd);
+        assert_eq!(frames[0].bytes, ACC.to_vec());
+        assert_eq!(frames[1].kind, FrameKind::HeartRate);
+        assert_eq!(*HeartRate::new(frames[1].bytes.clone()).unwrap().bpm(), 61);
+    }
+}
@@ -0,0 +1,89 @@
+use arctic::Error;
+use btleplug::api::{Central, Manager as _, Peripheral as _, ScanFilter};
+use btleplug::platform::Manager;
+use std::time::Duration;
+
+// How long to listen for advertisements
+const SCAN_TIME: Duration = Duration::from_secs(4);
+
+// A Polar device seen while scanning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub name: String,
+    pub id: String,
+    pub rssi: Option<i16>,
+}
+
+// Device IDs are the 8 hex characters printed on the sensor
+pub fn valid_id(id: &str) -> bool {
+    id.len() == 8 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Polar devices advertise as "Polar <model> <device id>"
+pub fn parse_name(name: &str) -> Option<String> {
+    if !name.starts_with("Polar") {
+        return None;
+    }
+    name.split_whitespace()
+        .last()
+        .filter(|id| valid_id(id))
+        .map(str::to_string)
+}
+
+// List nearby Polar devices, strongest signal first
+pub async fn scan() -> Result<Vec<Found>, Error> {
+    let manager = Manager::new().await.map_err(Error::BleError)?;
+    let central = manager
+        .adapters()
+        .await
+        .map_err(Error::BleError)?
+        .into_iter()
+        .next()
+        .ok_or(Error::NoBleAdaptor)?;
+
+    central
+        .start_scan(ScanFilter::default())
+        .await
+        .map_err(Error::BleError)?;
+    tokio::time::sleep(SCAN_TIME).await;
+    let peripherals = central.peripherals().await.map_err(Error::BleError)?;
+    let _ = central.stop_scan().await;
+
+    let mut found = vec![];
+    for p in peripherals {
+        let props = match p.properties().await {
+            Ok(Some(props)) => props,
+            _ => continue,
+        };
+        let name = props.local_name.unwrap_or_default();
+        if let Some(id) = parse_name(&name) {
+            found.push(Found {
+                name,
+                id,
+                rssi: props.rssi,
+            });
+        }
+    }
+    found.sort_by_key(|f| std::cmp::Reverse(f.rssi.unwrap_or(i16::MIN)));
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_ids() {
+        assert!(valid_id("7B45F72B"));
+        assert!(!valid_id("7B45F7"));
+        assert!(!valid_id("7B45F72G"));
+
+        assert_eq!(
+            parse_name("Polar H10 7B45F72B"),
+            Some("7B45F72B".to_string())
+        );
+        assert_eq!(parse_name("Polar H10"), None);
+        assert_eq!(parse_name("Garmin 7B45F72B"), None);
+    }
+}
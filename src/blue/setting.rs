@@ -1,11 +1,30 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
 // store what kind of measurements to keep
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Setting {
     pub hr: bool,
     pub ecg: bool,
     pub acc: bool,
     pub range: u8,
     pub rate: u8,
+    pub format: Format,
+    pub compression: Compression,
+    pub rotation: Rotation,
+    pub raw: bool,
+    pub rr_layout: RrLayout,
+    pub timing: Timing,
+    pub record_for: RecordFor,
+    // seconds to count down after connecting before measuring starts
+    pub delay: u8,
+    // connection attempts before giving up
+    pub attempts: u8,
+    // seconds before connecting is given up on
+    pub timeout: u16,
+    // seconds the live ECG graph shows
+    pub chart_window: u8,
 }
 
 impl Default for Setting {
@@ -22,6 +41,290 @@ impl Setting {
             acc,
             range,
             rate,
+            format: Format::default(),
+            compression: Compression::default(),
+            rotation: Rotation::default(),
+            raw: false,
+            rr_layout: RrLayout::default(),
+            timing: Timing::default(),
+            record_for: RecordFor::default(),
+            delay: 0,
+            attempts: 5,
+            timeout: 60,
+            chart_window: 5,
+        }
+    }
+}
+
+// file format measurements are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    #[default]
+    Csv,
+    Parquet,
+    Sqlite,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Csv, Format::Parquet, Format::Sqlite];
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Csv => "CSV",
+            Format::Parquet => "Parquet",
+            Format::Sqlite => "SQLite",
+        })
+    }
+}
+
+// compression applied to csv output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 3] = [Compression::None, Compression::Gzip, Compression::Zstd];
+
+    // file extension added after `.csv`
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
         }
     }
 }
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "No compression",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+// when to start a new csv file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+// toml can't hold `Size(100)` directly, so it is stored as `{ kind = "Size", value = 100 }`
+#[serde(tag = "kind", content = "value")]
+pub enum Rotation {
+    #[default]
+    Never,
+    // size in megabytes
+    Size(u64),
+    // time in minutes
+    Time(u64),
+}
+
+impl Rotation {
+    pub const ALL: [Rotation; 5] = [
+        Rotation::Never,
+        Rotation::Size(100),
+        Rotation::Size(500),
+        Rotation::Time(15),
+        Rotation::Time(60),
+    ];
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rotation::Never => write!(f, "Never rotate"),
+            Rotation::Size(mb) => write!(f, "Rotate every {} MB", mb),
+            Rotation::Time(min) => write!(f, "Rotate every {} min", min),
+        }
+    }
+}
+
+// how rr intervals are laid out in hr csv files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RrLayout {
+    // legacy layout, every interval gets its own trailing column
+    #[default]
+    Columns,
+    // one row per rr interval, timed by when that interval ended
+    Rows,
+    // all intervals of a notification as one quoted list
+    List,
+}
+
+impl RrLayout {
+    pub const ALL: [RrLayout; 3] = [RrLayout::Columns, RrLayout::Rows, RrLayout::List];
+
+    // name written in the hr file header
+    pub fn key(&self) -> &'static str {
+        match self {
+            RrLayout::Rows => "rows",
+            RrLayout::List => "list",
+            RrLayout::Columns => "columns",
+        }
+    }
+}
+
+impl fmt::Display for RrLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RrLayout::Rows => "One row per RR interval",
+            RrLayout::List => "RR intervals as a quoted list",
+            RrLayout::Columns => "RR intervals as extra columns (legacy)",
+        })
+    }
+}
+
+// how long a recording runs before it stops by itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum RecordFor {
+    #[default]
+    UntilStopped,
+    Minutes(u16),
+}
+
+impl RecordFor {
+    pub const ALL: [RecordFor; 7] = [
+        RecordFor::UntilStopped,
+        RecordFor::Minutes(1),
+        RecordFor::Minutes(2),
+        RecordFor::Minutes(5),
+        RecordFor::Minutes(10),
+        RecordFor::Minutes(30),
+        RecordFor::Minutes(60),
+    ];
+
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        match self {
+            RecordFor::UntilStopped => None,
+            RecordFor::Minutes(min) => Some(std::time::Duration::from_secs(*min as u64 * 60)),
+        }
+    }
+}
+
+impl fmt::Display for RecordFor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordFor::UntilStopped => write!(f, "Record until stopped"),
+            RecordFor::Minutes(min) => write!(f, "Record for {} min", min),
+        }
+    }
+}
+
+// how ECG and acceleration samples inside a frame are timed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Timing {
+    // spread between the timestamps of consecutive frames, which belong to their last sample
+    #[default]
+    Interpolated,
+    // legacy, the frame timestamp is the first sample and samples follow at the nominal rate
+    Nominal,
+}
+
+impl Timing {
+    pub const ALL: [Timing; 2] = [Timing::Interpolated, Timing::Nominal];
+
+    // name written in the `#pmd` line
+    pub fn key(&self) -> &'static str {
+        match self {
+            Timing::Interpolated => "interpolated",
+            Timing::Nominal => "nominal",
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Timing::Interpolated => "Sample times from the sensor clock",
+            Timing::Nominal => "Sample times from the nominal rate (legacy)",
+        })
+    }
+}
+
+// ECG always streams at this rate, arctic does not let it be changed
+pub const ECG_RATE: u8 = 130;
+
+// Measurement settings the sensor was actually started with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PmdSettings {
+    // acceleration range (G) and sample rate (Hz)
+    pub range: Option<u8>,
+    pub rate: Option<u8>,
+    pub ecg_rate: Option<u8>,
+    // whether the sensor confirmed it supports these values
+    pub verified: bool,
+    pub timing: Timing,
+}
+
+impl PmdSettings {
+    pub fn new(settings: Setting) -> Self {
+        Self {
+            range: settings.acc.then_some(settings.range),
+            rate: settings.acc.then_some(settings.rate),
+            ecg_rate: settings.ecg.then_some(ECG_RATE),
+            verified: false,
+            timing: settings.timing,
+        }
+    }
+}
+
+// Written as a `#pmd=...` line in csv files
+impl fmt::Display for PmdSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let num = |n: Option<u8>| n.map(|n| n.to_string()).unwrap_or_default();
+        writeln!(
+            f,
+            "#pmd=range={};rate={};ecg_rate={};verified={};timing={}",
+            num(self.range),
+            num(self.rate),
+            num(self.ecg_rate),
+            self.verified,
+            self.timing.key()
+        )
+    }
+}
+
+// Check a requested value against the values a sensor reports it supports
+pub fn check_supported(what: &str, requested: u8, supported: &[u8]) -> Result<(), String> {
+    if supported.is_empty() || supported.contains(&requested) {
+        return Ok(());
+    }
+    let supported: Vec<_> = supported.iter().map(u8::to_string).collect();
+    Err(format!(
+        "{} {} is not supported by this sensor (supported: {})",
+        what,
+        requested,
+        supported.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_values() {
+        assert!(check_supported("Acceleration range", 8, &[2, 4, 8]).is_ok());
+        assert!(check_supported("ECG sample rate", 130, &[]).is_ok());
+        assert_eq!(
+            check_supported("Acceleration sample rate", 25, &[50, 100, 200]).unwrap_err(),
+            "Acceleration sample rate 25 is not supported by this sensor (supported: 50, 100, 200)"
+        );
+    }
+
+    #[test]
+    fn pmd_line() {
+        let pmd = PmdSettings::new(Setting::new(true, true, false, 4, 50));
+        assert_eq!(pmd.range, None);
+        assert_eq!(
+            pmd.to_string(),
+            "#pmd=range=;rate=;ecg_rate=130;verified=false;timing=interpolated\n"
+        );
+    }
+}
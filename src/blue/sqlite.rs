@@ -0,0 +1,517 @@
+use super::fs::{hr_timestamp, rr_timestamps, MeasureType, Sample};
+use crate::menu::Meta;
+use arctic::HeartRate;
+use rusqlite::{params, Connection};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::Error;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS participants (
+    id TEXT PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    participant TEXT NOT NULL REFERENCES participants(id),
+    session TEXT NOT NULL,
+    UNIQUE(participant, session)
+);
+CREATE TABLE IF NOT EXISTS trials (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    trial TEXT NOT NULL,
+    date TEXT NOT NULL,
+    description TEXT NOT NULL,
+    hr INTEGER NOT NULL,
+    ecg INTEGER NOT NULL,
+    acc INTEGER NOT NULL,
+    range INTEGER NOT NULL,
+    rate INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS hr_samples (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    time INTEGER NOT NULL,
+    bpm INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS rr_samples (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    time INTEGER NOT NULL,
+    rr INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS ecg_samples (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    time INTEGER NOT NULL,
+    val INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS acc_samples (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    time INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    z INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS devices (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    device_id TEXT NOT NULL,
+    battery INTEGER,
+    manufacturer TEXT,
+    model TEXT,
+    firmware TEXT,
+    serial TEXT
+);
+CREATE TABLE IF NOT EXISTS trial_fields (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    name TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS markers (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    time INTEGER NOT NULL,
+    label TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS gaps (
+    trial_id INTEGER NOT NULL REFERENCES trials(id),
+    stream TEXT NOT NULL,
+    time INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS hr_samples_trial ON hr_samples(trial_id, time);
+CREATE INDEX IF NOT EXISTS rr_samples_trial ON rr_samples(trial_id, time);
+CREATE INDEX IF NOT EXISTS ecg_samples_trial ON ecg_samples(trial_id, time);
+CREATE INDEX IF NOT EXISTS acc_samples_trial ON acc_samples(trial_id, time);
+";
+
+// Open the study database and make sure all tables exist
+pub fn open(path: &str) -> Result<Connection, rusqlite::Error> {
+    let conn = Connection::open(path)?;
+    // each strap has its own connection, so wait for the others' commits
+    // and let readers in while writing
+    conn.busy_timeout(Duration::from_secs(10))?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+// Add participant, session and trial rows for this recording, returning the trial id
+fn insert_trial(conn: &Connection, metadata: &Meta) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO participants (id) VALUES (?1)",
+        params![metadata.id],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO sessions (participant, session) VALUES (?1, ?2)",
+        params![metadata.id, metadata.session],
+    )?;
+    let session_id: i64 = conn.query_row(
+        "SELECT id FROM sessions WHERE participant = ?1 AND session = ?2",
+        params![metadata.id, metadata.session],
+        |row| row.get(0),
+    )?;
+
+    let settings = &metadata.settings;
+    conn.execute(
+        "INSERT INTO trials (session_id, trial, date, description, hr, ecg, acc, range, rate)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            session_id,
+            metadata.trial,
+            metadata.date.to_rfc3339(),
+            metadata.description,
+            settings.hr,
+            settings.ecg,
+            settings.acc,
+            settings.range,
+            settings.rate,
+        ],
+    )?;
+
+    let trial = conn.last_insert_rowid();
+
+    for (name, value) in &metadata.fields {
+        conn.execute(
+            "INSERT INTO trial_fields (trial_id, name, value) VALUES (?1, ?2, ?3)",
+            params![trial, name, value],
+        )?;
+    }
+
+    if let Some(device) = &metadata.device {
+        conn.execute(
+            "INSERT INTO devices (trial_id, device_id, battery, manufacturer, model, firmware, serial)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                trial,
+                device.id,
+                device.battery,
+                device.manufacturer,
+                device.model,
+                device.firmware,
+                device.serial,
+            ],
+        )?;
+    }
+
+    Ok(trial)
+}
+
+// Rows are committed together once this many are waiting, like parquet row groups
+const BATCH_SIZE: usize = 10_000;
+// or once the oldest of them has waited this long, so a crash loses little
+const BATCH_AGE: Duration = Duration::from_secs(5);
+
+// Rows waiting for the next commit
+#[derive(Default)]
+struct Batch {
+    hr: Vec<(i64, u8)>,
+    rr: Vec<(i64, u16)>,
+    ecg: Vec<(i64, i32)>,
+    acc: Vec<(i64, i16, i16, i16)>,
+    gaps: Vec<(MeasureType, i64)>,
+    since: Option<Instant>,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.hr.len() + self.rr.len() + self.ecg.len() + self.acc.len() + self.gaps.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() >= BATCH_SIZE || self.since.is_some_and(|since| since.elapsed() >= BATCH_AGE)
+    }
+}
+
+// Connection to the study database, opened once data arrives
+pub struct Database {
+    metadata: Meta,
+    path: String,
+    conn: Option<Connection>,
+    trial: Option<i64>,
+    // streams whose next sample is the first after a reconnect
+    pending_gaps: [bool; 3],
+    batch: Batch,
+}
+
+impl Database {
+    pub fn new(metadata: Meta, path: String) -> Self {
+        Self {
+            metadata,
+            path,
+            conn: None,
+            trial: None,
+            pending_gaps: [false; 3],
+            batch: Batch::default(),
+        }
+    }
+
+    pub fn mark_gap(&mut self, ty: MeasureType) {
+        self.pending_gaps[ty.index()] = true;
+    }
+
+    fn take_gap(&mut self, ty: MeasureType, time: i64) {
+        if std::mem::take(&mut self.pending_gaps[ty.index()]) {
+            self.batch.gaps.push((ty, time));
+        }
+    }
+
+    fn conn(&mut self) -> Result<(&mut Connection, i64), rusqlite::Error> {
+        if self.conn.is_none() {
+            let conn = open(&self.path)?;
+            // a reopened connection keeps adding to the same trial
+            if self.trial.is_none() {
+                self.trial = Some(insert_trial(&conn, &self.metadata)?);
+            }
+            self.conn = Some(conn);
+        }
+        let trial = self.trial.expect("trial was inserted with the connection");
+        Ok((
+            self.conn.as_mut().expect("connection was just opened"),
+            trial,
+        ))
+    }
+
+    fn push_hr(&mut self, time: i64, bpm: u8, rr: &[u16]) -> Result<(), rusqlite::Error> {
+        self.take_gap(MeasureType::Hr, time);
+        self.batch.hr.push((time, bpm));
+        let times = rr_timestamps(time as u64, rr);
+        self.batch
+            .rr
+            .extend(times.into_iter().map(|t| t as i64).zip(rr.iter().copied()));
+        self.pushed()
+    }
+
+    fn push_samples(&mut self, samples: &[(u64, Sample)]) -> Result<(), rusqlite::Error> {
+        for (timestamp, sample) in samples {
+            let time = *timestamp as i64;
+            match *sample {
+                Sample::Ecg(val) => {
+                    self.take_gap(MeasureType::Ecg, time);
+                    self.batch.ecg.push((time, val));
+                }
+                Sample::Acc(x, y, z) => {
+                    self.take_gap(MeasureType::Acc, time);
+                    self.batch.acc.push((time, x, y, z));
+                }
+            }
+        }
+        self.pushed()
+    }
+
+    fn pushed(&mut self) -> Result<(), rusqlite::Error> {
+        self.batch.since.get_or_insert_with(Instant::now);
+        if self.batch.is_full() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Commit all waiting rows in one transaction
+    fn flush(&mut self) -> Result<(), rusqlite::Error> {
+        if self.batch.len() == 0 {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let (conn, trial) = self.conn()?;
+        let tx = conn.transaction()?;
+        for (ty, time) in &batch.gaps {
+            insert_gap(&tx, trial, *ty, *time)?;
+        }
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO hr_samples (trial_id, time, bpm) VALUES (?1, ?2, ?3)",
+            )?;
+            for (time, bpm) in &batch.hr {
+                stmt.execute(params![trial, time, bpm])?;
+            }
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO rr_samples (trial_id, time, rr) VALUES (?1, ?2, ?3)",
+            )?;
+            for (time, rr) in &batch.rr {
+                stmt.execute(params![trial, time, rr])?;
+            }
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO ecg_samples (trial_id, time, val) VALUES (?1, ?2, ?3)",
+            )?;
+            for (time, val) in &batch.ecg {
+                stmt.execute(params![trial, time, val])?;
+            }
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO acc_samples (trial_id, time, x, y, z) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (time, x, y, z) in &batch.acc {
+                stmt.execute(params![trial, time, x, y, z])?;
+            }
+        }
+        tx.commit()
+    }
+
+    fn insert_markers(&mut self, markers: &[(u64, String)]) -> Result<(), rusqlite::Error> {
+        let (conn, trial) = self.conn()?;
+        let tx = conn.transaction()?;
+        for (time, label) in markers {
+            tx.execute(
+                "INSERT INTO markers (trial_id, time, label) VALUES (?1, ?2, ?3)",
+                params![trial, *time as i64, label],
+            )?;
+        }
+        tx.commit()
+    }
+
+    // Store how long data was recorded for with the other trial fields
+    fn record_duration(&mut self, secs: f64) -> Result<(), rusqlite::Error> {
+        self.flush()?;
+        if self.trial.is_none() {
+            return Ok(());
+        }
+        let (conn, trial) = self.conn()?;
+        conn.execute(
+            "INSERT INTO trial_fields (trial_id, name, value) VALUES (?1, 'duration', ?2)",
+            params![trial, format!("{:.3}", secs)],
+        )?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), rusqlite::Error> {
+        let res = self.flush();
+        self.conn = None;
+        res
+    }
+
+    // Id of the trial written to, None until data arrived
+    pub fn trial(&self) -> Option<i64> {
+        self.trial
+    }
+}
+
+// Run database work on the blocking pool, so the event loop isn't held up by commits
+async fn with_db<T: Send + 'static>(
+    db: &Arc<Mutex<Database>>,
+    f: impl FnOnce(&mut Database) -> Result<T, rusqlite::Error> + Send + 'static,
+) -> Result<T, Error> {
+    let db = Arc::clone(db);
+    tokio::task::spawn_blocking(move || f(&mut db.lock().expect("stupid mutex")))
+        .await
+        .map_err(Error::other)?
+        .map_err(Error::other)
+}
+
+// Write hr data, return last data for sending
+pub async fn write_hr(
+    data: HeartRate,
+    db: &Arc<Mutex<Database>>,
+    start: &Mutex<Option<u64>>,
+) -> Result<(u8, String), Error> {
+    let timestamp = hr_timestamp(start) as i64;
+    let bpm = *data.bpm();
+    let rr = data.rr().clone().unwrap_or_default();
+    let rr_text: String = rr.iter().map(|i| format!(",{}", i)).collect();
+
+    with_db(db, move |db| db.push_hr(timestamp, bpm, &rr)).await?;
+
+    Ok((bpm, rr_text))
+}
+
+// Write ecg/acc data, return last acceleration for sending
+pub async fn write_data(
+    samples: Vec<(u64, Sample)>,
+    db: &Arc<Mutex<Database>>,
+) -> Result<Option<(i16, i16, i16)>, Error> {
+    let last = samples.iter().rev().find_map(|(_, sample)| match sample {
+        Sample::Acc(x, y, z) => Some((*x, *y, *z)),
+        Sample::Ecg(_) => None,
+    });
+    with_db(db, move |db| db.push_samples(&samples)).await?;
+
+    Ok(last)
+}
+
+pub async fn insert_markers(
+    db: &Arc<Mutex<Database>>,
+    markers: Vec<(u64, String)>,
+) -> Result<(), Error> {
+    with_db(db, move |db| db.insert_markers(&markers)).await
+}
+
+pub async fn record_duration(db: &Arc<Mutex<Database>>, secs: f64) -> Result<(), Error> {
+    with_db(db, move |db| db.record_duration(secs)).await
+}
+
+// Commit the rows still waiting and let go of the database file
+pub async fn close(db: &Arc<Mutex<Database>>) -> Result<(), Error> {
+    with_db(db, Database::close).await
+}
+
+// Record the first sample time after a reconnect
+fn insert_gap(
+    conn: &Connection,
+    trial: i64,
+    ty: MeasureType,
+    time: i64,
+) -> Result<(), rusqlite::Error> {
+    let stream = match ty {
+        MeasureType::Hr => "hr",
+        MeasureType::Ecg => "ecg",
+        MeasureType::Acc => "acc",
+    };
+    conn.execute(
+        "INSERT INTO gaps (trial_id, stream, time) VALUES (?1, ?2, ?3)",
+        params![trial, stream, time],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blue::info::DeviceInfo;
+
+    #[test]
+    fn insert_trials() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+
+        let meta = Meta {
+            id: "p1".to_string(),
+            session: "1".to_string(),
+            trial: "1".to_string(),
+            device: Some(DeviceInfo {
+                id: "7B45F72B".to_string(),
+                battery: Some(80),
+                ..DeviceInfo::default()
+            }),
+            fields: vec![("group".to_string(), "control".to_string())],
+            ..Meta::default()
+        };
+        let first = insert_trial(&conn, &meta).unwrap();
+        let second = insert_trial(
+            &conn,
+            &Meta {
+                trial: "2".to_string(),
+                ..meta.clone()
+            },
+        )
+        .unwrap();
+
+        assert_ne!(first, second);
+        let sessions: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sessions, 1);
+        let trial: String = conn
+            .query_row(
+                "SELECT trial FROM trials WHERE id = ?1",
+                params![second],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(trial, "2");
+        let battery: u8 = conn
+            .query_row(
+                "SELECT battery FROM devices WHERE trial_id = ?1",
+                params![second],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(battery, 80);
+        let group: String = conn
+            .query_row(
+                "SELECT value FROM trial_fields WHERE trial_id = ?1 AND name = 'group'",
+                params![second],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(group, "control");
+    }
+
+    #[test]
+    fn batched_rows_committed_on_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("study.db");
+        let mut db = Database::new(Meta::default(), path.to_str().unwrap().to_string());
+
+        db.mark_gap(MeasureType::Hr);
+        db.push_hr(3_000_000_000, 60, &[1000, 500]).unwrap();
+        db.push_samples(&[(10, Sample::Ecg(5)), (20, Sample::Acc(1, 2, 3))])
+            .unwrap();
+        // nothing is written before the batch fills up
+        assert_eq!(db.trial(), None);
+        db.close().unwrap();
+
+        let conn = open(path.to_str().unwrap()).unwrap();
+        let trial = db.trial().unwrap();
+        let rr: Vec<(i64, u16)> = conn
+            .prepare("SELECT time, rr FROM rr_samples WHERE trial_id = ?1 ORDER BY time")
+            .unwrap()
+            .query_map(params![trial], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rr, vec![(2_500_000_000, 1000), (3_000_000_000, 500)]);
+        let counts: (i64, i64, i64) = conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM ecg_samples), (SELECT COUNT(*) FROM acc_samples),
+                 (SELECT COUNT(*) FROM gaps WHERE stream = 'hr')",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(counts, (1, 1, 1));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{blue::setting::Setting, menu::Paths};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CONFIG_NAME: &str = "config.toml";
+// iced's default window size
+const WINDOW: (u32, u32) = (1024, 768);
+
+// Preferences kept between launches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // last device connected to
+    pub device_id: String,
+    pub window: (u32, u32),
+    pub settings: Setting,
+    pub paths: Paths,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            device_id: "".to_string(),
+            window: WINDOW,
+            settings: Setting::default(),
+            paths: Paths::default(),
+        }
+    }
+}
+
+// Per-user directory for the config file and profiles
+pub fn config_dir() -> PathBuf {
+    dirs_next::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("polar-arctic")
+}
+
+fn config_path() -> PathBuf {
+    config_dir().join(CONFIG_NAME)
+}
+
+impl Config {
+    // Last saved preferences, or the defaults on the first launch or if the file is broken
+    pub fn load() -> Self {
+        let text = match fs::read_to_string(config_path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::warn!("config could not be read: {}", e);
+                return Self::default();
+            }
+        };
+        Self::parse(&text).unwrap_or_else(|e| {
+            log::warn!("config is not valid, using defaults: {}", e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = config_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, to_toml(self)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+// Every `.toml` file in `dir` that `read` accepts, sorted by `name`. Broken files are
+// skipped with a warning calling them `kind`.
+pub fn list_toml<T>(
+    dir: &Path,
+    kind: &str,
+    read: impl Fn(&Path) -> io::Result<T>,
+    name: impl Fn(&T) -> &str,
+) -> Vec<T> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut items: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|e| match read(&e.path()) {
+            Ok(item) => Some(item),
+            Err(err) => {
+                log::warn!("{} {} skipped: {}", kind, e.path().display(), err);
+                None
+            }
+        })
+        .collect();
+    items.sort_by(|a, b| name(a).cmp(name(b)));
+    items
+}
+
+// Going through `Value` puts plain values before tables, which toml requires
+pub fn to_toml<T: Serialize>(value: &T) -> io::Result<String> {
+    toml::Value::try_from(value)
+        .and_then(|value| toml::to_string_pretty(&value))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blue::setting::{Format, Rotation};
+
+    #[test]
+    fn round_trip() {
+        let mut config = Config {
+            device_id: "7B45F72B".to_string(),
+            window: (1280, 900),
+            ..Config::default()
+        };
+        config.settings.ecg = true;
+        config.settings.format = Format::Parquet;
+        config.settings.rotation = Rotation::Size(100);
+        config.paths.ecg = "output/ecg.parquet".to_string();
+
+        let text = to_toml(&config).unwrap();
+        assert_eq!(Config::parse(&text).unwrap(), config);
+
+        // settings missing from older config files keep their defaults
+        let old = Config::parse("device_id = \"7B45F72B\"\n[settings]\nrate = 50\n").unwrap();
+        assert_eq!(old.settings.rate, 50);
+        assert_eq!(old.settings.range, Setting::default().range);
+        assert_eq!(old.window, WINDOW);
+    }
+}
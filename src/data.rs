@@ -1,31 +1,52 @@
+use iced::canvas::{event::Status, Cursor, Event};
 use iced::pure::{button, column, row, text_input, widget::Text, Pure, State};
-use iced::{Column, Length, Row, Rule};
+use iced::{button, mouse, Button, Column, Length, Rectangle, Row, Rule};
 use plotters::prelude::*;
 use plotters_iced::{Chart, ChartWidget, DrawingBackend};
-use rev_lines::RevLines;
 use std::collections::VecDeque;
-use std::fs::File;
-use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tokio::sync::watch::Receiver;
 
-use super::{modal::PopupMessage, Message, WhichView};
+use super::{
+    blue::{info::DeviceInfo, loss::Loss, scan::Found, setting::PmdSettings, ConnectionState},
+    export::{image_path, save, ImageFormat},
+    modal::PopupMessage,
+    protocol::Protocol,
+    timer::{clock, Timer},
+    Message, WhichView,
+};
 
 pub struct Data {
-    chart: EcgChart,
     device_id: String,
+    participant: String,
+    trial: String,
     state: State,
-    recent_data: Recent,
-    receiver: Option<DataReceiver>,
+    panels: Vec<Panel>,
+    devices: Vec<Found>,
+    scanning: bool,
+    connecting: bool,
+    // started when the first strap connects
+    timer: Option<Timer>,
+    // protocol picked on the menu and the phase that was started last
+    protocol: Option<Protocol>,
+    phase: Option<usize>,
 }
 
 impl Default for Data {
     fn default() -> Self {
         Self {
-            chart: EcgChart::new().unwrap(),
             device_id: "".to_string(),
+            participant: "".to_string(),
+            trial: "".to_string(),
             state: State::new(),
-            recent_data: Recent::default(),
-            receiver: None,
+            panels: vec![],
+            devices: vec![],
+            scanning: false,
+            connecting: false,
+            timer: None,
+            protocol: None,
+            phase: None,
         }
     }
 }
@@ -35,11 +56,68 @@ impl Data {
         Self::default()
     }
 
-    pub fn take_receivers(&mut self, receiver: DataReceiver) {
-        self.receiver = Some(receiver);
+    // Show live data for a strap that is being connected, with a graph when it records
+    // ecg to the given file
+    pub fn add_panel(&mut self, receiver: DataReceiver, ecg: Option<String>, window: u8) {
+        let mut chart = EcgChart::new(window);
+        chart.path = ecg;
+        self.panels.push(Panel {
+            device_id: self.device_id.to_uppercase(),
+            participant: self.participant.clone(),
+            chart,
+            recent_data: Recent::default(),
+            receiver,
+            pause: button::State::new(),
+            export: [button::State::new(), button::State::new()],
+        });
+    }
+
+    // Point a strap's chart at the ecg file of a new trial, if it has one
+    pub fn follow_ecg(&mut self, index: usize, path: String) {
+        if let Some(panel) = self.panels.get_mut(index) {
+            if panel.chart.path.is_some() {
+                panel.chart.path = Some(path);
+                panel.chart.data_points.clear();
+                panel.chart.frozen = None;
+            }
+        }
+    }
+
+    // Save what a strap's graph shows next to its ecg file
+    pub fn export_chart(&self, index: usize, format: ImageFormat) -> Result<PathBuf, String> {
+        let chart = &self.panels.get(index).ok_or("no such graph")?.chart;
+        let ecg = Path::new(chart.path.as_deref().ok_or("the graph has no ecg file")?);
+        let name = ecg.file_stem().unwrap_or_default().to_string_lossy();
+        let path = image_path(
+            ecg.parent().unwrap_or_else(|| Path::new(".")),
+            &format!("{}-graph", name),
+            format,
+        );
+        save(chart, &path, format)?;
+        Ok(path)
     }
 
-    pub fn view(&mut self) -> iced::Element<Message> {
+    // Sensor details and data loss of a strap, for its report
+    pub fn device(&self, index: usize) -> (Option<DeviceInfo>, Loss) {
+        match self.panels.get(index) {
+            Some(panel) => (panel.receiver.info(), panel.receiver.loss()),
+            None => (None, Loss::default()),
+        }
+    }
+
+    // Freeze or unfreeze a strap's graph, recording goes on either way
+    pub fn pause_chart(&mut self, index: usize) {
+        if let Some(panel) = self.panels.get_mut(index) {
+            panel.chart.pause();
+        }
+    }
+
+    // Drop the panel of a strap that failed to connect
+    pub fn remove_last_panel(&mut self) {
+        self.panels.pop();
+    }
+
+    pub fn view(&mut self) -> iced::Element<'_, Message> {
         let back = button(Text::new("Back to menu").size(20))
             .on_press(Message::SwitchView(WhichView::Menu))
             .padding(15);
@@ -53,8 +131,92 @@ impl Data {
             .padding(15)
             .size(20)
             .on_submit(Message::CreateSensor);
+        let participant = text_input("Participant ID", &self.participant, Message::NewParticipant)
+            .padding(15)
+            .size(20)
+            .on_submit(Message::CreateSensor);
+
+        let scan_button = if self.scanning {
+            button(Text::new("Scanning..."))
+        } else {
+            button(Text::new("Scan for devices")).on_press(Message::Scan)
+        };
+
+        // Click a device to connect to it
+        let devices = self.devices.iter().fold(column().spacing(5), |col, dev| {
+            let rssi = dev
+                .rssi
+                .map(|r| format!("{} dBm", r))
+                .unwrap_or_else(|| "unknown".to_string());
+            col.push(
+                button(Text::new(format!(
+                    "{}  (ID: {}, RSSI: {})",
+                    dev.name, dev.id, rssi
+                )))
+                .on_press(Message::SelectDevice(dev.id.clone())),
+            )
+        });
+
+        let cancel_button = if self.connecting {
+            button(Text::new("Cancel connecting")).on_press(Message::CancelConnect)
+        } else {
+            button(Text::new("Cancel connecting"))
+        };
 
         let stop_button = button(Text::new("Stop Measurement")).on_press(Message::StopMeasurement);
+        let next_button = if self.panels.is_empty() || self.connecting {
+            button(Text::new("Next trial"))
+        } else {
+            button(Text::new("Next trial")).on_press(Message::NextTrial)
+        };
+        let trial = Text::new(format!("Trial {}", self.trial)).size(20);
+        let timer = Text::new(match &self.timer {
+            Some(timer) => timer.text(Instant::now()),
+            None => "Not recording".to_string(),
+        })
+        .size(20);
+
+        // The running phase with its instructions for the participant
+        let protocol = match (&self.protocol, &self.timer) {
+            (Some(protocol), Some(timer)) => {
+                let now = Instant::now();
+                match timer
+                    .running(now)
+                    .and_then(|elapsed| protocol.phase_at(elapsed))
+                {
+                    Some((i, left)) => {
+                        let phase = &protocol.phases[i];
+                        column()
+                            .spacing(10)
+                            .push(
+                                Text::new(format!(
+                                    "Phase {} of {}: {} ({} left)",
+                                    i + 1,
+                                    protocol.phases.len(),
+                                    phase.name,
+                                    clock(left)
+                                ))
+                                .size(25),
+                            )
+                            .push(Text::new(&phase.instructions).size(35))
+                    }
+                    None if timer.countdown(now).is_zero() => {
+                        column().push(Text::new(format!("{} finished", protocol.name)).size(25))
+                    }
+                    None => column().push(
+                        Text::new(format!(
+                            "{} starts with {}",
+                            protocol.name, protocol.phases[0].name
+                        ))
+                        .size(25),
+                    ),
+                }
+            }
+            (Some(protocol), None) => {
+                column().push(Text::new(format!("Protocol: {}", protocol.name)).size(25))
+            }
+            _ => column(),
+        };
 
         let view = column()
             .spacing(20)
@@ -62,27 +224,240 @@ impl Data {
             .max_width(1000)
             .push(header)
             .push(Rule::horizontal(10))
-            .push(input)
-            .push(stop_button);
+            .push(
+                row()
+                    .spacing(20)
+                    .push(input)
+                    .push(participant)
+                    .push(scan_button),
+            )
+            .push(devices)
+            .push(
+                row()
+                    .spacing(20)
+                    .push(cancel_button)
+                    .push(stop_button)
+                    .push(next_button)
+                    .push(trial)
+                    .push(timer),
+            )
+            .push(protocol);
 
         let pure = Pure::new(&mut self.state, view);
 
+        self.panels
+            .iter_mut()
+            .enumerate()
+            .fold(Column::new().spacing(20).push(pure), |col, (i, panel)| {
+                col.push(Rule::horizontal(10)).push(panel.view(i))
+            })
+            .into()
+    }
+
+    pub fn update_id(&mut self, msg: String) {
+        self.device_id = msg;
+    }
+
+    pub fn id(&mut self) -> &mut String {
+        &mut self.device_id
+    }
+
+    pub fn update_participant(&mut self, msg: String) {
+        self.participant = msg;
+    }
+
+    pub fn participant(&self) -> &str {
+        &self.participant
+    }
+
+    pub fn is_connecting(&self) -> bool {
+        self.connecting
+    }
+
+    pub fn set_connecting(&mut self, connecting: bool) {
+        self.connecting = connecting;
+    }
+
+    pub fn set_scanning(&mut self, scanning: bool) {
+        self.scanning = scanning;
+    }
+
+    pub fn set_devices(&mut self, devices: Vec<Found>) {
+        self.scanning = false;
+        self.devices = devices;
+    }
+
+    // Start the recording timer if this is the first strap, returns how long the strap
+    // should wait so every strap starts measuring together
+    pub fn start_timer(&mut self, delay: Duration, length: Option<Duration>) -> Duration {
+        let now = Instant::now();
+        self.timer
+            .get_or_insert_with(|| Timer::new(now, delay, length))
+            .countdown(now)
+    }
+
+    pub fn set_trial(&mut self, trial: String) {
+        self.trial = trial;
+    }
+
+    // Forget the timer and protocol progress of the last trial
+    pub fn reset_timer(&mut self) {
+        self.timer = None;
+        self.phase = None;
+    }
+
+    pub fn set_protocol(&mut self, protocol: Option<Protocol>) {
+        self.protocol = protocol;
+    }
+
+    // A protocol decides the recording length itself
+    pub fn protocol_length(&self) -> Option<Duration> {
+        self.protocol.as_ref().map(Protocol::length)
+    }
+
+    // Name of the protocol phase that has just started, if one did
+    pub fn next_phase(&mut self) -> Option<String> {
+        let protocol = self.protocol.as_ref()?;
+        let elapsed = self.timer.as_ref()?.running(Instant::now())?;
+        let (phase, _) = protocol.phase_at(elapsed)?;
+        if self.phase == Some(phase) {
+            return None;
+        }
+        self.phase = Some(phase);
+        Some(protocol.phases[phase].name.clone())
+    }
+
+    // True once, when a protocol that was running is over
+    pub fn end_protocol(&mut self) -> bool {
+        self.phase.take().is_some()
+    }
+
+    // True once, when a timed recording is over
+    pub fn finish_timer(&mut self) -> bool {
+        self.timer
+            .as_mut()
+            .is_some_and(|timer| timer.finish(Instant::now()))
+    }
+
+    pub fn stop_timer(&mut self) {
+        if let Some(timer) = &mut self.timer {
+            timer.stop(Instant::now());
+        }
+    }
+
+    pub fn update(&mut self) {
+        for panel in &mut self.panels {
+            panel.update();
+        }
+    }
+}
+
+// Live data from one strap
+struct Panel {
+    device_id: String,
+    participant: String,
+    chart: EcgChart,
+    recent_data: Recent,
+    receiver: DataReceiver,
+    pause: button::State,
+    export: [button::State; 2],
+}
+
+impl Panel {
+    fn view(&mut self, index: usize) -> iced::Element<'_, Message> {
+        let title = iced::Text::new(format!(
+            "Device {} (participant {})",
+            self.device_id, self.participant
+        ))
+        .size(25);
+        let status = iced::Text::new(&self.recent_data.status);
+        let connection = iced::Text::new(format!("Connection: {}", self.recent_data.state));
+        let device = match &self.recent_data.info {
+            Some(info) => {
+                let text = |s: &Option<String>| s.clone().unwrap_or_else(|| "?".to_string());
+                let battery = info
+                    .battery
+                    .map(|b| format!("{}%", b))
+                    .unwrap_or_else(|| "?".to_string());
+                let device = iced::Text::new(format!(
+                    "Battery: {}{}    Firmware: {}    Serial: {}",
+                    battery,
+                    if info.low_battery() {
+                        " (low, charge before long sessions)"
+                    } else {
+                        ""
+                    },
+                    text(&info.firmware),
+                    text(&info.serial)
+                ));
+                if info.low_battery() {
+                    device.color([0.8, 0.0, 0.0])
+                } else {
+                    device
+                }
+            }
+            None => iced::Text::new("Battery: ?"),
+        };
+        let pmd = iced::Text::new(match &self.recent_data.pmd {
+            Some(pmd) => {
+                let mut parts = vec![];
+                if let (Some(range), Some(rate)) = (pmd.range, pmd.rate) {
+                    parts.push(format!("acceleration ±{} G at {} Hz", range, rate));
+                }
+                if let Some(rate) = pmd.ecg_rate {
+                    parts.push(format!("ECG at {} Hz", rate));
+                }
+                format!(
+                    "Measuring: {}{}",
+                    parts.join(", "),
+                    if pmd.verified { "" } else { " (unverified)" }
+                )
+            }
+            None => "Measuring: heart rate only".to_string(),
+        });
+        let loss = self.recent_data.loss;
+        let mut lost = vec![];
+        if loss.ecg.received > 0 {
+            lost.push(format!("ECG {}", loss.ecg));
+        }
+        if loss.acc.received > 0 {
+            lost.push(format!("acceleration {}", loss.acc));
+        }
+        let loss = iced::Text::new(format!(
+            "Data loss: {}",
+            if lost.is_empty() {
+                "-".to_string()
+            } else {
+                lost.join(", ")
+            }
+        ));
+        let loss = if self.recent_data.loss.ecg.missing + self.recent_data.loss.acc.missing > 0 {
+            loss.color([0.8, 0.5, 0.0])
+        } else {
+            loss
+        };
+
         let rr_text = &self.recent_data.rr;
         let mut rr_text = rr_text.chars();
         rr_text.next();
 
-        let bpm = iced::Text::new(&format!("Heart rate (BPM): {}", self.recent_data.bpm));
-        let rr = iced::Text::new(&format!(
+        let bpm = iced::Text::new(format!("Heart rate (BPM): {}", self.recent_data.bpm));
+        let rr = iced::Text::new(format!(
             "RR interval (µV): {}",
             rr_text.as_str().replace(',', ", ")
         ));
         let acc_title = iced::Text::new("Acceleration (mG):");
-        let x = iced::Text::new(&format!("    X: {}", self.recent_data.x));
-        let y = iced::Text::new(&format!("    Y: {}", self.recent_data.y));
-        let z = iced::Text::new(&format!("    Z: {}", self.recent_data.z));
+        let x = iced::Text::new(format!("    X: {}", self.recent_data.x));
+        let y = iced::Text::new(format!("    Y: {}", self.recent_data.y));
+        let z = iced::Text::new(format!("    Z: {}", self.recent_data.z));
 
         let data_column = Column::new()
             .spacing(20)
+            .push(status)
+            .push(connection)
+            .push(device)
+            .push(pmd)
+            .push(loss)
             .push(bpm)
             .push(rr)
             .push(acc_title)
@@ -90,59 +465,91 @@ impl Data {
             .push(y)
             .push(z);
 
-        let data = Row::new()
-            .spacing(20)
-            .push(self.chart.view())
-            .push(data_column);
-
-        Column::new().spacing(20).push(pure).push(data).into()
-    }
-
-    pub fn update_id(&mut self, msg: String) {
-        self.device_id = msg;
-    }
+        // the graph only exists when ecg is recorded
+        let graph = if self.chart.path.is_some() {
+            let pause = Button::new(
+                &mut self.pause,
+                iced::Text::new(if self.chart.paused() {
+                    "Resume graph"
+                } else {
+                    "Pause graph"
+                }),
+            )
+            .on_press(Message::PauseChart(index));
+            let buttons = self.export.iter_mut().zip(ImageFormat::ALL).fold(
+                Row::new().spacing(10).push(pause),
+                |row, (state, format)| {
+                    row.push(
+                        Button::new(state, iced::Text::new(format!("Save {}", format)))
+                            .on_press(Message::ExportChart(index, format)),
+                    )
+                },
+            );
+            Column::new()
+                .spacing(10)
+                .push(self.chart.view())
+                .push(buttons)
+                .push(iced::Text::new("Scroll to zoom, drag to look back"))
+        } else {
+            Column::new().push(self.chart.view())
+        };
 
-    pub fn id(&mut self) -> &mut String {
-        &mut self.device_id
-    }
+        let data = Row::new().spacing(20).push(graph).push(data_column);
 
-    pub fn update(&mut self) {
-        self.chart.update();
-        if let Some(rx) = &self.receiver {
-            self.recent_data.bpm = rx.hr();
-            self.recent_data.rr = rx.rr();
-            let (x, y, z) = rx.acc();
-            self.recent_data.x = x;
-            self.recent_data.y = y;
-            self.recent_data.z = z;
-        }
+        Column::new().spacing(20).push(title).push(data).into()
     }
 
-    pub fn set_path(&mut self, path: String) {
-        self.chart.path = Some(path)
+    fn update(&mut self) {
+        let rx = &self.receiver;
+        self.chart.update(rx);
+        self.recent_data.bpm = rx.hr();
+        self.recent_data.rr = rx.rr();
+        let (x, y, z) = rx.acc();
+        self.recent_data.x = x;
+        self.recent_data.y = y;
+        self.recent_data.z = z;
+        self.recent_data.status = rx.status();
+        self.recent_data.state = rx.state();
+        self.recent_data.info = rx.info();
+        self.recent_data.pmd = rx.pmd();
+        self.recent_data.loss = rx.loss();
     }
 }
 
+// Seconds of ECG kept for panning back through
+const HISTORY: u64 = 60;
+// Shortest time the graph can be zoomed in to, in seconds
+const MIN_WINDOW: f64 = 0.5;
+const NANOS: f64 = 1e9;
+
 // Store chart data
 #[derive(Default)]
 struct EcgChart {
+    // oldest first, at most HISTORY seconds
     data_points: VecDeque<(u64, i32)>,
+    // ecg file the graph is saved next to
     pub path: Option<String>,
+    // seconds shown
+    window: f64,
+    // end of the view while the display is paused, nothing new is read then
+    frozen: Option<u64>,
+    // cursor x and end of the view when dragging started
+    drag: Option<(f32, u64)>,
 }
 
 impl EcgChart {
-    pub fn new() -> Result<EcgChart, Box<dyn std::error::Error>> {
-        let mut chart = Self {
-            data_points: VecDeque::with_capacity(200),
+    pub fn new(window: u8) -> EcgChart {
+        Self {
+            data_points: VecDeque::new(),
             path: None,
-        };
-        chart.update_data()?;
-
-        Ok(chart)
+            window: (window as f64).max(MIN_WINDOW),
+            frozen: None,
+            drag: None,
+        }
     }
 
     // Draw chart
-    fn view(&mut self) -> iced::Element<Message> {
+    fn view(&mut self) -> iced::Element<'_, Message> {
         let chart = ChartWidget::new(self)
             .width(Length::Units(400))
             .height(Length::Units(400));
@@ -150,66 +557,173 @@ impl EcgChart {
         chart.into()
     }
 
-    // Get initial data from file
-    fn update_data(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let path = if let Some(path) = &self.path {
-            path
-        } else {
-            return Ok(());
-        };
-        let file = File::open(path)?;
-        let records = RevLines::with_capacity(400, BufReader::new(file))?;
-
-        for record in records.take(200) {
-            if record.contains("time") || record.contains("UTC") {
-                continue;
+    // Add the samples that arrived since the last update, oldest first in `recent`
+    fn update_data(&mut self, recent: &VecDeque<(u64, i32)>) {
+        let last = self.data_points.back().map(|p| p.0);
+        // a new trial starts its clock over
+        if let (Some(last), Some(newest)) = (last, recent.back()) {
+            if newest.0 < last {
+                self.data_points.clear();
             }
-            let mut val = record.split(',');
-            let time = val.next().unwrap();
-            let ecg = val.next().unwrap();
-            self.push((time.parse::<u64>()?, ecg.parse::<i32>()?));
         }
-
-        Ok(())
+        let last = self.data_points.back().map(|p| p.0);
+        let new = recent
+            .iter()
+            .rev()
+            .take_while(|p| last.is_none_or(|last| p.0 > last))
+            .count();
+        for &point in recent.iter().skip(recent.len() - new) {
+            self.push(point);
+        }
     }
 
-    // Add to back and pop off front
+    // Add to back and drop what is older than HISTORY
     fn push(&mut self, val: (u64, i32)) {
-        self.data_points.push_front(val);
-        while self.data_points.len() > 200 {
-            self.data_points.pop_back();
+        self.data_points.push_back(val);
+        while self
+            .data_points
+            .front()
+            .is_some_and(|p| val.0.saturating_sub(p.0) > HISTORY * NANOS as u64)
+        {
+            self.data_points.pop_front();
         }
     }
 
-    // Update data - remove data from the end and add to the start
-    fn update(&mut self) {
-        if let Err(e) = self.update_data() {
-            eprintln!("Error getting data: {}", e);
+    fn newest(&self) -> u64 {
+        self.data_points.back().map_or(0, |p| p.0)
+    }
+
+    // Time range shown
+    fn view_range(&self) -> (u64, u64) {
+        let end = self.frozen.unwrap_or_else(|| self.newest());
+        (end.saturating_sub((self.window * NANOS) as u64), end)
+    }
+
+    // Zoom in for positive steps, out for negative ones
+    fn zoom(&mut self, steps: f64) {
+        self.window = (self.window * 0.8f64.powf(steps)).clamp(MIN_WINDOW, HISTORY as f64);
+    }
+
+    // Move the end of the view back by `fraction` of the window from `end`, which pauses the display
+    fn pan(&mut self, end: u64, fraction: f64) {
+        let oldest = self.data_points.front().map_or(0, |p| p.0);
+        let newest = self.newest();
+        let earliest = (oldest + (self.window * NANOS) as u64).min(newest);
+        let moved = (fraction * self.window * NANOS) as i64;
+        let end = (end as i64 - moved).clamp(earliest as i64, newest as i64);
+        self.frozen = Some(end as u64);
+    }
+
+    // Freeze the display, or go back to following the recording with fresh data
+    fn pause(&mut self) {
+        if self.frozen.take().is_some() {
+            self.data_points.clear();
+        } else {
+            self.frozen = Some(self.newest());
+        }
+    }
+
+    fn paused(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    // Update data - recording goes on while the display is paused
+    fn update(&mut self, receiver: &DataReceiver) {
+        if self.paused() || self.path.is_none() {
+            return;
         }
+        self.update_data(&receiver.ecg.borrow());
     }
 }
 
 impl Chart<Message> for EcgChart {
     // Create plotters chart
     fn build_chart<DB: DrawingBackend>(&self, mut builder: ChartBuilder<DB>) {
+        let (start, end) = self.view_range();
+        let newest = self.newest();
+        // seconds before the newest sample
+        let seconds = |time: u64| -((newest - time) as f64) / NANOS;
+
+        let visible: Vec<_> = self
+            .data_points
+            .iter()
+            .filter(|p| p.0 >= start && p.0 <= end)
+            .map(|&(time, ecg)| (seconds(time), ecg))
+            .collect();
+
+        // fit the samples shown, at least 200 µV high
+        let (low, high) = visible.iter().fold((i32::MAX, i32::MIN), |(lo, hi), p| {
+            (lo.min(p.1), hi.max(p.1))
+        });
+        let (low, high) = if visible.is_empty() {
+            (-1000, 1000)
+        } else {
+            let pad = ((high - low) / 10).max(100);
+            (low - pad, high + pad)
+        };
+
         let mut ctx = builder
-            .set_label_area_size(LabelAreaPosition::Bottom, -181)
-            .set_label_area_size(LabelAreaPosition::Left, 40)
+            .set_label_area_size(LabelAreaPosition::Bottom, 40)
+            .set_label_area_size(LabelAreaPosition::Left, 60)
             .caption("ECG Data", ("sans-serif", 30u32))
-            .build_cartesian_2d(0..200u64, -1000..1000i32)
+            .build_cartesian_2d(
+                seconds(start)..seconds(end).max(seconds(start) + 1e-3),
+                low..high,
+            )
             .unwrap();
 
         ctx.configure_mesh()
             .set_tick_mark_size(LabelAreaPosition::Bottom, 5)
+            .x_desc("Time (s)")
+            .y_desc("ECG (µV)")
             .draw()
             .unwrap();
 
-        let series = LineSeries::new(
-            (0..self.data_points.len() as u64).map(|p| (p, self.data_points[p as usize].1)),
-            &BLACK,
-        );
+        ctx.draw_series(LineSeries::new(visible, &BLACK))
+            .expect("Error making graph");
+    }
 
-        ctx.draw_series(series).expect("Error making graph");
+    // Wheel zooms, dragging pans back through the history
+    fn update(
+        &mut self,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (Status, Option<Message>) {
+        let status = match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) if cursor.is_over(&bounds) => {
+                let steps = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / 50.0,
+                };
+                self.zoom(steps as f64);
+                Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                match cursor.position_in(&bounds) {
+                    Some(position) => {
+                        self.drag = Some((position.x, self.view_range().1));
+                        Status::Captured
+                    }
+                    None => Status::Ignored,
+                }
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => match self.drag {
+                Some((x, end)) => {
+                    self.pan(end, ((position.x - bounds.x - x) / bounds.width) as f64);
+                    Status::Captured
+                }
+                None => Status::Ignored,
+            },
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+                if self.drag.is_some() =>
+            {
+                self.drag = None;
+                Status::Captured
+            }
+            _ => Status::Ignored,
+        };
+        (status, None)
     }
 }
 
@@ -220,6 +734,11 @@ pub struct Recent {
     pub x: i16,
     pub y: i16,
     pub z: i16,
+    pub status: String,
+    pub state: ConnectionState,
+    pub info: Option<DeviceInfo>,
+    pub pmd: Option<PmdSettings>,
+    pub loss: Loss,
 }
 
 // Instead of reading the output files, get messages containing the data
@@ -227,11 +746,38 @@ pub struct DataReceiver {
     hr: Receiver<u8>,
     rr: Receiver<String>,
     acc: Receiver<(i16, i16, i16)>,
+    ecg: Receiver<VecDeque<(u64, i32)>>,
+    status: Receiver<String>,
+    state: Receiver<ConnectionState>,
+    info: Receiver<Option<DeviceInfo>>,
+    pmd: Receiver<Option<PmdSettings>>,
+    loss: Receiver<Loss>,
 }
 
 impl DataReceiver {
-    pub fn new(hr: Receiver<u8>, rr: Receiver<String>, acc: Receiver<(i16, i16, i16)>) -> Self {
-        Self { hr, rr, acc }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        hr: Receiver<u8>,
+        rr: Receiver<String>,
+        acc: Receiver<(i16, i16, i16)>,
+        ecg: Receiver<VecDeque<(u64, i32)>>,
+        status: Receiver<String>,
+        state: Receiver<ConnectionState>,
+        info: Receiver<Option<DeviceInfo>>,
+        pmd: Receiver<Option<PmdSettings>>,
+        loss: Receiver<Loss>,
+    ) -> Self {
+        Self {
+            hr,
+            rr,
+            acc,
+            ecg,
+            status,
+            state,
+            info,
+            pmd,
+            loss,
+        }
     }
 
     pub fn hr(&self) -> u8 {
@@ -245,4 +791,77 @@ impl DataReceiver {
     pub fn acc(&self) -> (i16, i16, i16) {
         *self.acc.borrow()
     }
+
+    pub fn status(&self) -> String {
+        self.status.borrow().clone()
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub fn info(&self) -> Option<DeviceInfo> {
+        self.info.borrow().clone()
+    }
+
+    pub fn pmd(&self) -> Option<PmdSettings> {
+        *self.pmd.borrow()
+    }
+
+    pub fn loss(&self) -> Loss {
+        *self.loss.borrow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chart_window() {
+        let secs = |s: u64| s * 1_000_000_000;
+        let mut chart = EcgChart::new(5);
+        for s in 0..=100 {
+            chart.push((secs(s), s as i32));
+        }
+        // only the last minute is kept
+        assert_eq!(chart.data_points.front().unwrap().0, secs(40));
+        assert_eq!(chart.view_range(), (secs(95), secs(100)));
+
+        chart.zoom(-100.0);
+        assert_eq!(chart.window, 60.0);
+        chart.zoom(100.0);
+        assert_eq!(chart.window, MIN_WINDOW);
+        chart.window = 10.0;
+
+        // dragging right by half the graph goes back half a window and pauses
+        chart.pan(secs(100), 0.5);
+        assert!(chart.paused());
+        assert_eq!(chart.view_range(), (secs(85), secs(95)));
+        chart.pan(secs(95), 100.0);
+        assert_eq!(chart.view_range(), (secs(40), secs(50)));
+        chart.pan(secs(50), -100.0);
+        assert_eq!(chart.view_range(), (secs(90), secs(100)));
+
+        chart.pause();
+        assert!(!chart.paused() && chart.data_points.is_empty());
+        chart.pause();
+        assert!(chart.paused());
+    }
+
+    #[test]
+    fn chart_samples() {
+        let mut chart = EcgChart::new(5);
+        let mut recent: VecDeque<_> = (1..=3).map(|t| (t, t as i32)).collect();
+        chart.update_data(&recent);
+        recent.extend([(4, 4), (5, 5)]);
+        recent.pop_front();
+        chart.update_data(&recent);
+        let times: Vec<_> = chart.data_points.iter().map(|p| p.0).collect();
+        assert_eq!(times, [1, 2, 3, 4, 5]);
+
+        // samples of the next trial start from zero again
+        chart.update_data(&VecDeque::from([(0, 0), (1, 1)]));
+        assert_eq!(chart.data_points, [(0, 0), (1, 1)]);
+    }
 }
@@ -0,0 +1,137 @@
+use chrono::Local;
+use plotters::prelude::*;
+use plotters_iced::Chart;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use super::Message;
+
+// Pixels of an exported chart
+const SIZE: (u32, u32) = (1200, 600);
+
+// File types a chart can be saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 2] = [ImageFormat::Png, ImageFormat::Svg];
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Svg => "svg",
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension().to_uppercase())
+    }
+}
+
+// Charts draw their text with the font the rest of the app uses
+pub fn register_font() {
+    static FONT: Once = Once::new();
+    FONT.call_once(|| {
+        let font = iced_graphics::font::FALLBACK;
+        if plotters::style::register_font("sans-serif", FontStyle::Normal, font).is_err() {
+            log::error!("the chart font could not be loaded");
+        }
+    });
+}
+
+// `<dir>/<name>-<local time>.<extension>`, so exports don't overwrite each other
+pub fn image_path(dir: &Path, name: &str, format: ImageFormat) -> PathBuf {
+    dir.join(format!(
+        "{}-{}.{}",
+        name,
+        Local::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    ))
+}
+
+// Draw a chart on a white background into an image file
+pub fn save<C: Chart<Message>>(chart: &C, path: &Path, format: ImageFormat) -> Result<(), String> {
+    register_font();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    match format {
+        ImageFormat::Png => {
+            let root = BitMapBackend::new(path, SIZE).into_drawing_area();
+            root.fill(&WHITE).map_err(|e| e.to_string())?;
+            chart.draw_chart(root.clone());
+            root.present().map_err(|e| e.to_string())
+        }
+        ImageFormat::Svg => {
+            let root = SVGBackend::new(path, SIZE).into_drawing_area();
+            root.fill(&WHITE).map_err(|e| e.to_string())?;
+            chart.draw_chart(root.clone());
+            root.present().map_err(|e| e.to_string())
+        }
+    }
+}
+
+// Draw a chart as SVG markup, to put it in a page
+pub fn svg<C: Chart<Message>>(chart: &C, size: (u32, u32)) -> Result<String, String> {
+    register_font();
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+        root.fill(&WHITE).map_err(|e| e.to_string())?;
+        chart.draw_chart(root.clone());
+        root.present().map_err(|e| e.to_string())?;
+    }
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plotters_iced::DrawingBackend;
+
+    struct Line;
+
+    impl Chart<Message> for Line {
+        fn build_chart<DB: DrawingBackend>(&self, mut builder: ChartBuilder<DB>) {
+            let mut ctx = builder
+                .set_label_area_size(LabelAreaPosition::Bottom, 40)
+                .set_label_area_size(LabelAreaPosition::Left, 60)
+                .build_cartesian_2d(0.0..10.0, -500.0..500.0)
+                .unwrap();
+            ctx.configure_mesh()
+                .x_desc("Time (s)")
+                .y_desc("ECG (µV)")
+                .draw()
+                .unwrap();
+            ctx.draw_series(LineSeries::new(
+                (0..100).map(|i| (i as f64 / 10.0, (i as f64).sin() * 400.0)),
+                &BLACK,
+            ))
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn png_and_svg() {
+        let tmp = tempfile::tempdir().unwrap();
+        // created by saving
+        let dir = tmp.path().join("graphs");
+
+        let png = image_path(&dir, "ecg-graph", ImageFormat::Png);
+        assert!(png.to_string_lossy().ends_with(".png"));
+        save(&Line, &png, ImageFormat::Png).unwrap();
+        assert!(fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+
+        let svg = image_path(&dir, "ecg-graph", ImageFormat::Svg);
+        save(&Line, &svg, ImageFormat::Svg).unwrap();
+        let text = fs::read_to_string(&svg).unwrap();
+        assert!(text.contains("<svg") && text.contains("Time (s)"));
+    }
+}
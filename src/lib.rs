@@ -4,46 +4,103 @@ use iced::{
     Application, Column, Command, Container, Element, Length, Rule, Subscription, Text,
 };
 use iced_aw::{pure::Card, Modal};
+use iced_native::{subscription::events_with, window, Event};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time;
 use tokio::sync::{
-    watch::{channel, Sender},
+    oneshot,
+    watch::{channel, Receiver, Sender},
     Mutex,
 };
 
 mod blue;
+mod config;
 mod data;
+mod export;
+mod log_file;
 mod menu;
 mod modal;
+mod notifications;
+mod profile;
+mod protocol;
+mod recording;
+mod report;
+mod review;
+mod schema;
+mod timer;
 
-use blue::{new_device, reset, setting::Setting, update, DataSender, SensorManager};
+pub use blue::raw;
+pub use config::Config;
+pub use log_file::init_logging;
+
+use blue::setting::{Compression, Format, RecordFor, Rotation, RrLayout, Timing};
+use blue::{
+    fs::hr_timestamp,
+    loss::Loss,
+    new_device, reset,
+    scan::{scan, valid_id, Found},
+    setting::Setting,
+    update, ConnectError, DataSender, Markers, SensorManager, SessionClock,
+};
 use data::Data;
-use menu::{Menu, Paths, Type, WhichMeta};
+use export::ImageFormat;
+use log_file::export_bundle;
+use menu::{Menu, Meta, Paths, Type, WhichMeta};
 use modal::{get_modal, PopupMessage};
+use notifications::Notifications;
+use profile::Profile;
+use protocol::Protocol;
+use recording::{Recording, Samples, Source};
+use report::Report;
+use review::{Browse, Review, WindowChange};
+use schema::Schema;
 
 // Main Application
 #[derive(Default)]
 pub struct App {
-    sensor_manager: Arc<Mutex<SensorManager>>,
+    straps: Vec<Strap>,
     view: Views,
     which_err: PopupMessage,
     modal_state: iced_aw::modal::State<State>,
     settings: Setting,
-    tx: Option<Sender<bool>>,
+    cancel: Option<Sender<bool>>,
     paths: Paths,
+    meta: Meta,
+    clock: SessionClock,
+    notifications: Notifications,
+    config: Config,
+    schema: Schema,
+    protocol: Option<Protocol>,
+    // a trial after the one submitted on the menu is being recorded
+    rerun: bool,
+    exit: bool,
+}
+
+// One sensor recording in this session, the last one may still be connecting
+struct Strap {
+    manager: Arc<Mutex<SensorManager>>,
+    tx: Sender<bool>,
+    // where the current trial's recording went, once it is complete
+    done: Option<oneshot::Receiver<Source>>,
+    device_id: String,
+    participant: String,
+    markers: Arc<Markers>,
 }
 
 // Possible views to show the user
 pub enum Views {
     Menu(Box<Menu>),
     Data(Box<Data>),
+    Browse(Box<Browse>),
 }
 
 impl Views {
-    fn view(&mut self) -> iced::Element<Message> {
+    fn view(&mut self) -> iced::Element<'_, Message> {
         match self {
             Views::Menu(menu) => menu.view(),
             Views::Data(data) => data.view(),
+            Views::Browse(browse) => browse.view(),
         }
     }
 }
@@ -58,15 +115,7 @@ impl Default for Views {
 pub enum WhichView {
     Menu,
     Data,
-}
-
-impl From<WhichView> for Views {
-    fn from(which: WhichView) -> Self {
-        match which {
-            WhichView::Menu => Views::Menu(Box::new(Menu::default())),
-            WhichView::Data => Views::Data(Box::new(Data::new())),
-        }
-    }
+    Browse,
 }
 
 #[derive(Debug, Clone)]
@@ -74,28 +123,225 @@ pub enum Message {
     None,
     Tick,
     NewDeviceID(String),
+    NewParticipant(String),
     CreateSensor,
+    Scan,
+    ScanResult(Result<Vec<Found>, String>),
+    SelectDevice(String),
+    CancelConnect,
+    ConnectDone(Option<PopupMessage>),
     NewMeta,
     ChangeMeta(WhichMeta, String),
+    ChangeField(usize, String),
     SwitchView(WhichView),
     CloseModal,
     Popup(PopupMessage),
-    Connected,
+    Connected(String),
     UpdateSelection(Type, bool),
     RangeChange(u8),
     RateChange(u8),
+    TimingChange(Timing),
+    RecordForChange(RecordFor),
+    DelayChange(u8),
+    AttemptsChange(u8),
+    TimeoutChange(u16),
+    ChartWindowChange(u8),
+    PauseChart(usize),
+    ExportChart(usize, ImageFormat),
+    ExportReview(ImageFormat),
+    FormatChange(Format),
+    CompressionChange(Compression),
+    RotationChange(Rotation),
+    RrLayoutChange(RrLayout),
     StopMeasurement,
+    NextTrial,
+    BrowseDir(String),
+    FindRecordings,
+    RecordingsFound(Vec<Recording>),
+    OpenRecording(usize),
+    RecordingLoaded(Result<Box<(Recording, Samples)>, String>),
+    CloseRecording,
+    ReviewWindow(WindowChange),
     SetPath(Type, String),
+    SetDbPath(String),
+    RawChange(bool),
+    SetRawPath(String),
+    ClearNotifications,
+    SelectProfile(String),
+    SelectProtocol(String),
+    ProfileName(String),
+    ProfilePath(String),
+    SaveProfile,
+    ImportProfile,
+    ExportProfile,
+    WindowResized(u32, u32),
+    CloseRequested,
+    ToggleLog,
+    ExportDiagnostics,
+    DiagnosticsExported(Result<String, String>),
+    ReportWritten(Result<PathBuf, String>),
+}
+
+// Output paths for a participant's strap in the current trial, `meta` is from the menu
+fn strap_paths(paths: &Paths, meta: &Meta, rerun: bool, participant: &str) -> Paths {
+    let strap = Meta {
+        id: participant.to_string(),
+        ..meta.clone()
+    };
+    let mut expanded = paths.expand(&strap);
+    if participant != meta.id && !paths.per_participant() {
+        expanded = expanded.for_participant(participant);
+    }
+    if rerun && !paths.per_trial() {
+        expanded = expanded.for_trial(&meta.trial);
+    }
+    expanded
+}
+
+// Wait out the start delay, false when measurement is stopped before it is over
+async fn count_down(delay: time::Duration, mut rx: Receiver<bool>) -> bool {
+    let stopped = async {
+        // a dropped sender belongs to a trial that was replaced
+        while *rx.borrow() && rx.changed().await.is_ok() {}
+    };
+    tokio::select! {
+        biased;
+        _ = stopped => false,
+        _ = tokio::time::sleep(delay) => true,
+    }
+}
+
+impl App {
+    // Remember the current settings for the next launch
+    fn save_config(&mut self) {
+        self.config.settings = self.settings;
+        self.config.paths = self.paths.clone();
+        if let Err(e) = self.config.save() {
+            self.notifications
+                .events()
+                .warn(format!("Settings could not be saved: {}", e));
+        }
+    }
+
+    // Add an event marker to the output of every strap
+    fn mark(&self, label: &str) {
+        let time = hr_timestamp(&self.clock);
+        for strap in &self.straps {
+            strap.markers.push(time, label.to_string());
+        }
+    }
+
+    // Write the report of a strap's recording once it has stopped
+    fn report(&mut self, index: usize) -> Command<Message> {
+        let done = match self.straps[index].done.take() {
+            Some(done) => done,
+            None => return Command::none(),
+        };
+        let strap = &self.straps[index];
+        let (info, loss) = match &self.view {
+            Views::Data(data) => data.device(index),
+            _ => (None, Loss::default()),
+        };
+        let report = Report {
+            meta: Meta {
+                id: strap.participant.clone(),
+                settings: self.settings,
+                ..self.meta.clone()
+            },
+            device_id: strap.device_id.clone(),
+            info,
+            loss,
+        };
+        let paths = strap_paths(&self.paths, &self.meta, self.rerun, &strap.participant);
+        let dir = PathBuf::from(paths.output_dir());
+        Command::perform(
+            async move {
+                // sent once the handler has closed its files
+                let source = done
+                    .await
+                    .map_err(|_| format!("nothing was recorded for {}", report.meta.id))?;
+                tokio::task::spawn_blocking(move || report.write(&source, &dir))
+                    .await
+                    .map_err(|e| e.to_string())?
+            },
+            Message::ReportWritten,
+        )
+    }
+
+    // Tell where exported charts were saved
+    fn exported(&self, res: Result<Vec<PathBuf>, String>) {
+        let events = self.notifications.events();
+        match res {
+            Ok(paths) => {
+                for path in paths {
+                    events.info(format!("Chart saved to {}", path.display()));
+                }
+            }
+            Err(e) => events.error(format!("Chart could not be saved: {}", e)),
+        }
+    }
+
+    // Plain text description of this run for the diagnostics bundle
+    fn diagnostics(&self) -> String {
+        let straps: String = self
+            .straps
+            .iter()
+            .map(|s| {
+                format!(
+                    "strap: device={} participant={}\n",
+                    s.device_id, s.participant
+                )
+            })
+            .collect();
+        format!(
+            "version: {}\nos: {} {}\nsettings: {:?}\npaths: {:?}\nparticipant: {} session: {} trial: {}\n{}\nnotifications:\n{}",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            self.settings,
+            self.paths,
+            self.meta.id,
+            self.meta.session,
+            self.meta.trial,
+            straps,
+            self.notifications.summary(),
+        )
+    }
 }
 
 impl Application for App {
     type Executor = executor::Default;
     type Message = Message;
 
-    type Flags = ();
+    type Flags = Config;
 
-    fn new(_flags: ()) -> (Self, Command<Message>) {
-        (App::default(), Command::none())
+    fn new(config: Config) -> (Self, Command<Message>) {
+        export::register_font();
+        let notifications = Notifications::default();
+        let schema = Schema::load().unwrap_or_else(|e| {
+            notifications
+                .events()
+                .error(format!("The metadata schema could not be used: {}", e));
+            Schema::default()
+        });
+        let app = App {
+            view: Views::Menu(Box::new(Menu::with_settings(
+                config.settings,
+                config.paths.clone(),
+                &schema,
+            ))),
+            settings: config.settings,
+            paths: config.paths.clone(),
+            config,
+            schema,
+            notifications,
+            ..App::default()
+        };
+        (app, Command::none())
+    }
+
+    fn should_exit(&self) -> bool {
+        self.exit
     }
 
     fn title(&self) -> String {
@@ -106,9 +352,27 @@ impl Application for App {
         match message {
             Message::None => Command::none(),
             Message::Tick => {
+                let (mut phase, mut finished) = (None, false);
                 if let Views::Data(data) = &mut self.view {
                     data.update();
+                    phase = data.next_phase();
+                    finished = data.finish_timer();
+                }
+                if let Some(phase) = phase {
+                    log::info!("protocol phase: {}", phase);
+                    self.mark(&phase);
+                    self.notifications
+                        .events()
+                        .info(format!("Phase started: {}", phase));
+                }
+                if finished {
+                    self.notifications
+                        .events()
+                        .info("Recording time is up, stopping".to_string());
+                    self.notifications.poll();
+                    return self.update(Message::StopMeasurement);
                 }
+                self.notifications.poll();
                 Command::none()
             }
             Message::NewDeviceID(msg) => {
@@ -117,49 +381,190 @@ impl Application for App {
                 }
                 Command::none()
             }
+            Message::NewParticipant(msg) => {
+                if let Views::Data(data) = &mut self.view {
+                    data.update_participant(msg);
+                }
+                Command::none()
+            }
+            Message::Scan => {
+                log::info!("user: scan");
+                if let Views::Data(data) = &mut self.view {
+                    data.set_scanning(true);
+                    return Command::perform(scan(), |res| {
+                        Message::ScanResult(res.map_err(|e| e.to_string()))
+                    });
+                }
+                Command::none()
+            }
+            Message::ScanResult(res) => {
+                if let Views::Data(data) = &mut self.view {
+                    match res {
+                        Ok(devices) => data.set_devices(devices),
+                        Err(e) => {
+                            data.set_devices(vec![]);
+                            return self.update(Message::Popup(PopupMessage::Polar(e)));
+                        }
+                    }
+                }
+                Command::none()
+            }
+            Message::SelectDevice(id) => {
+                if let Views::Data(data) = &mut self.view {
+                    data.update_id(id);
+                }
+                self.update(Message::CreateSensor)
+            }
             Message::CreateSensor => {
                 // Replace with new using user selected options
                 if let Views::Data(data) = &mut self.view {
+                    if data.is_connecting() {
+                        return Command::none();
+                    }
+                    if !valid_id(data.id()) {
+                        return self.update(Message::Popup(PopupMessage::DeviceID));
+                    }
+                    let device_id = data.id().to_uppercase();
+                    let participant = match data.participant().trim() {
+                        "" => self.meta.id.clone(),
+                        id => id.replace(',', "-"),
+                    };
+                    // every strap needs its own device and its own output files
+                    if self
+                        .straps
+                        .iter()
+                        .any(|s| s.device_id == device_id || s.participant == participant)
+                    {
+                        return self.update(Message::Popup(PopupMessage::InUse));
+                    }
+                    data.update_participant(participant.clone());
+                    log::info!("user: connect {} for {}", device_id, participant);
+
                     let (tx, rx) = channel(true);
-                    self.tx = Some(tx);
+                    let (done_tx, done_rx) = oneshot::channel();
+                    let (cancel_tx, cancel_rx) = channel(false);
+                    self.cancel = Some(cancel_tx);
+                    data.set_connecting(true);
                     let set = self.settings;
-                    let paths = self.paths.clone();
-                    let other_me = Arc::clone(&self.sensor_manager);
-                    let (send, recv) = DataSender::init_transmitters();
-                    data.take_receivers(recv);
+                    let meta = Meta {
+                        id: participant.clone(),
+                        ..self.meta.clone()
+                    };
+                    // the menu's participant uses the paths from the menu, which already exist
+                    let new_files = participant != self.meta.id || self.rerun;
+                    let paths = strap_paths(&self.paths, &self.meta, self.rerun, &participant);
+                    let clock = Arc::clone(&self.clock);
+                    let markers = Arc::new(Markers::default());
+                    let other_me = Arc::new(Mutex::new(SensorManager::default()));
+                    self.straps.push(Strap {
+                        manager: Arc::clone(&other_me),
+                        tx,
+                        done: Some(done_rx),
+                        device_id: device_id.clone(),
+                        participant,
+                        markers: Arc::clone(&markers),
+                    });
+                    let (send, recv) = DataSender::init_transmitters(
+                        self.notifications.events().with_source(&device_id),
+                    );
+                    let ecg = set.ecg.then(|| paths.ecg_output(set.format).to_string());
+                    data.add_panel(recv, ecg, set.chart_window);
+                    let connected = device_id.clone();
                     Command::perform(
-                        new_device(
-                            data.id().clone(),
-                            Setting::new(set.hr, set.ecg, set.acc, set.range, set.rate),
-                            rx,
-                            paths,
-                            send,
-                        ),
+                        async move {
+                            if new_files {
+                                update(set, meta.clone(), paths.clone())
+                                    .await
+                                    .map_err(ConnectError::Output)?;
+                            }
+                            new_device(
+                                device_id, set, rx, done_tx, cancel_rx, meta, paths, send, clock,
+                                markers,
+                            )
+                            .await
+                        },
                         move |res| match res {
-                            Ok(sensor) => {
-                                futures::executor::block_on(other_me.lock()).sensor = Some(sensor);
-                                Message::Popup(PopupMessage::Connected)
+                            Ok((sensor, link)) => {
+                                let mut manager = futures::executor::block_on(other_me.lock());
+                                manager.sensor = Some(sensor);
+                                manager.link = Some(link);
+                                Message::ConnectDone(Some(PopupMessage::Connected(
+                                    connected.clone(),
+                                )))
+                            }
+                            Err(ConnectError::Cancelled) => Message::ConnectDone(None),
+                            Err(e) => {
+                                Message::ConnectDone(Some(PopupMessage::Polar(e.to_string())))
                             }
-                            Err(e) => Message::Popup(PopupMessage::Polar(e.to_string())),
                         },
                     )
                 } else {
                     Command::none()
                 }
             }
+            Message::CancelConnect => {
+                if let Some(cancel) = self.cancel.take() {
+                    log::info!("user: cancel connection");
+                    // the connection attempt may already be over
+                    let _ = cancel.send(true);
+                }
+                Command::none()
+            }
+            Message::ConnectDone(popup) => {
+                self.cancel = None;
+                let connected = matches!(popup, Some(PopupMessage::Connected(_)));
+                if let Some(PopupMessage::Connected(id)) = &popup {
+                    self.config.device_id = id.clone();
+                    self.save_config();
+                } else {
+                    self.straps.pop();
+                }
+                if let Views::Data(data) = &mut self.view {
+                    data.set_connecting(false);
+                    if !connected {
+                        data.remove_last_panel();
+                    }
+                }
+                match popup {
+                    Some(popup) => self.update(Message::Popup(popup)),
+                    None => Command::none(),
+                }
+            }
             Message::NewMeta => {
                 if let Views::Menu(meta) = &mut self.view {
                     if let Err(which) = meta.verify() {
                         self.update(Message::Popup(which.into()));
+                    } else if let Err(e) = meta.verify_fields() {
+                        self.update(Message::Popup(PopupMessage::Field(e)));
                     } else {
-                        let data = meta.meta_state.meta_data.clone();
+                        let mut data = meta.meta_state.meta_data.clone();
+                        let protocol = meta.protocol();
+                        if let Some(protocol) = &protocol {
+                            data.fields.push((
+                                "protocol".to_string(),
+                                protocol.name.replace([',', ';', '='], "-"),
+                            ));
+                        }
+                        log::info!(
+                            "user: submit participant={} session={} trial={} format={}",
+                            data.id,
+                            data.session,
+                            data.trial,
+                            data.settings.format
+                        );
                         let set = self.settings;
-                        let paths = meta.meta_state.paths.clone();
-                        let ecg = paths.ecg.clone();
+                        let paths = meta.meta_state.paths.expand(&data);
+                        self.meta = data.clone();
+                        self.clock = SessionClock::default();
+                        self.rerun = false;
+                        self.save_config();
                         self.update(Message::SwitchView(WhichView::Data));
-                        if let Views::Data(data) = &mut self.view {
-                            data.set_path(ecg);
+                        if let Views::Data(view) = &mut self.view {
+                            view.update_participant(data.id.clone());
+                            view.set_protocol(protocol.clone());
+                            view.set_trial(data.trial.clone());
                         }
+                        self.protocol = protocol;
                         return Command::perform(update(set, data, paths), |res| {
                             if let Err(err) = res {
                                 Message::Popup(PopupMessage::Io(err.to_string()))
@@ -177,18 +582,54 @@ impl Application for App {
                 }
                 Command::none()
             }
+            Message::ChangeField(index, value) => {
+                if let Views::Menu(meta) = &mut self.view {
+                    meta.change_field(index, value);
+                }
+                Command::none()
+            }
             Message::SwitchView(view) => {
-                self.view = view.into();
-                if let WhichView::Menu = view {
-                    self.update(Message::StopMeasurement);
-                    let other_me = Arc::clone(&self.sensor_manager);
-                    Command::perform(reset(other_me), |res| {
-                        if let Err(e) = res {
-                            Message::Popup(PopupMessage::Polar(e.to_string()))
-                        } else {
-                            Message::None
+                log::info!("user: switch to {:?}", view);
+                self.update(Message::CancelConnect);
+                // stop while the data screen still has the timer, protocol and data loss
+                let stop = match view {
+                    WhichView::Menu => self.update(Message::StopMeasurement),
+                    _ => Command::none(),
+                };
+                self.view = match view {
+                    WhichView::Menu => {
+                        let mut menu =
+                            Menu::with_settings(self.settings, self.paths.clone(), &self.schema);
+                        if let Some(protocol) = &self.protocol {
+                            menu.select_protocol(&protocol.name);
                         }
-                    })
+                        Views::Menu(Box::new(menu))
+                    }
+                    WhichView::Data => {
+                        let mut data = Data::new();
+                        data.update_id(self.config.device_id.clone());
+                        data.set_protocol(self.protocol.clone());
+                        data.set_trial(self.meta.trial.clone());
+                        Views::Data(Box::new(data))
+                    }
+                    WhichView::Browse => {
+                        Views::Browse(Box::new(Browse::new(self.paths.output_dir())))
+                    }
+                };
+                if let WhichView::Browse = view {
+                    return self.update(Message::FindRecordings);
+                }
+                if let WhichView::Menu = view {
+                    let resets = self.straps.drain(..).map(|strap| {
+                        Command::perform(reset(strap.manager), |res| {
+                            if let Err(e) = res {
+                                Message::Popup(PopupMessage::Polar(e.to_string()))
+                            } else {
+                                Message::None
+                            }
+                        })
+                    });
+                    Command::batch(std::iter::once(stop).chain(resets))
                 } else {
                     Command::none()
                 }
@@ -198,18 +639,42 @@ impl Application for App {
                 Command::none()
             }
             Message::Popup(which) => {
+                // keep errors around after the popup is closed
+                if let PopupMessage::Polar(e) | PopupMessage::Io(e) = &which {
+                    self.notifications.events().error(e.clone());
+                }
                 self.modal_state.show(true);
                 self.which_err = which;
-                if let PopupMessage::Connected = &self.which_err {
-                    self.update(Message::Connected)
+                if let PopupMessage::Connected(id) = &self.which_err {
+                    self.update(Message::Connected(id.clone()))
                 } else {
                     Command::none()
                 }
             }
-            Message::Connected => {
-                let other_me = Arc::clone(&self.sensor_manager);
+            Message::Connected(id) => {
+                let (other_me, rx) = match self.straps.iter().find(|s| s.device_id == id) {
+                    Some(strap) => (Arc::clone(&strap.manager), strap.tx.subscribe()),
+                    None => return Command::none(),
+                };
+                let delay = match &mut self.view {
+                    Views::Data(data) => {
+                        let length = data
+                            .protocol_length()
+                            .or(self.settings.record_for.duration());
+                        data.start_timer(
+                            time::Duration::from_secs(self.settings.delay as u64),
+                            length,
+                        )
+                    }
+                    _ => time::Duration::ZERO,
+                };
                 Command::perform(
-                    tokio::spawn(async move { other_me.lock().await.start().await }),
+                    tokio::spawn(async move {
+                        if !count_down(delay, rx).await {
+                            return Ok(());
+                        }
+                        other_me.lock().await.start().await
+                    }),
                     |res| {
                         if let Err(e) = res {
                             Message::Popup(PopupMessage::Polar(e.to_string()))
@@ -252,9 +717,260 @@ impl Application for App {
                 }
                 Command::none()
             }
+            Message::TimingChange(timing) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.timing = timing;
+                    menu.meta_state.meta_data.settings.timing = timing;
+                }
+                Command::none()
+            }
+            Message::RecordForChange(record_for) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.record_for = record_for;
+                    menu.meta_state.meta_data.settings.record_for = record_for;
+                }
+                Command::none()
+            }
+            Message::DelayChange(delay) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.delay = delay;
+                    menu.meta_state.meta_data.settings.delay = delay;
+                }
+                Command::none()
+            }
+            Message::ChartWindowChange(window) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.chart_window = window;
+                    menu.meta_state.meta_data.settings.chart_window = window;
+                }
+                Command::none()
+            }
+            Message::PauseChart(index) => {
+                if let Views::Data(data) = &mut self.view {
+                    data.pause_chart(index);
+                }
+                Command::none()
+            }
+            Message::ExportChart(index, format) => {
+                log::info!("user: export graph {} as {}", index, format);
+                if let Views::Data(data) = &self.view {
+                    let res = data.export_chart(index, format).map(|p| vec![p]);
+                    self.exported(res);
+                }
+                Command::none()
+            }
+            Message::ExportReview(format) => {
+                log::info!("user: export review charts as {}", format);
+                if let Views::Browse(browse) = &self.view {
+                    let res = browse.export(format);
+                    self.exported(res);
+                }
+                Command::none()
+            }
+            Message::AttemptsChange(attempts) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.attempts = attempts;
+                    menu.meta_state.meta_data.settings.attempts = attempts;
+                }
+                Command::none()
+            }
+            Message::TimeoutChange(timeout) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.timeout = timeout;
+                    menu.meta_state.meta_data.settings.timeout = timeout;
+                }
+                Command::none()
+            }
+            Message::FormatChange(format) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.format = format;
+                    menu.meta_state.meta_data.settings.format = format;
+                }
+                Command::none()
+            }
+            Message::CompressionChange(compression) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.compression = compression;
+                    menu.meta_state.meta_data.settings.compression = compression;
+                }
+                Command::none()
+            }
+            Message::RotationChange(rotation) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.rotation = rotation;
+                    menu.meta_state.meta_data.settings.rotation = rotation;
+                }
+                Command::none()
+            }
+            Message::RrLayoutChange(layout) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.rr_layout = layout;
+                    menu.meta_state.meta_data.settings.rr_layout = layout;
+                }
+                Command::none()
+            }
             Message::StopMeasurement => {
-                if let Some(tx) = &self.tx {
-                    tx.send(false).expect("Unable to send stop signal????");
+                log::info!("user: stop");
+                let ended = match &mut self.view {
+                    Views::Data(data) => {
+                        data.stop_timer();
+                        data.end_protocol()
+                    }
+                    _ => false,
+                };
+                if ended {
+                    self.mark("end");
+                }
+                let mut reports = vec![];
+                for i in 0..self.straps.len() {
+                    // the strap may have stopped on its own already
+                    if *self.straps[i].tx.borrow() {
+                        reports.push(self.report(i));
+                    }
+                    let _ = self.straps[i].tx.send(false);
+                }
+                Command::batch(reports)
+            }
+            Message::NextTrial => {
+                if self.straps.is_empty() || self.cancel.is_some() {
+                    return Command::none();
+                }
+                let stop = self.update(Message::StopMeasurement);
+                self.meta.trial = menu::next_trial(&self.meta.trial);
+                self.meta.date = chrono::Utc::now();
+                self.clock = SessionClock::default();
+                self.rerun = true;
+                log::info!("user: next trial {}", self.meta.trial);
+                self.notifications
+                    .events()
+                    .info(format!("Starting trial {}", self.meta.trial));
+
+                let delay = time::Duration::from_secs(self.settings.delay as u64);
+                let delay = match &mut self.view {
+                    Views::Data(data) => {
+                        data.set_trial(self.meta.trial.clone());
+                        data.reset_timer();
+                        let length = data
+                            .protocol_length()
+                            .or(self.settings.record_for.duration());
+                        data.start_timer(delay, length)
+                    }
+                    _ => delay,
+                };
+
+                let mut commands = vec![stop];
+                for i in 0..self.straps.len() {
+                    let paths = strap_paths(
+                        &self.paths,
+                        &self.meta,
+                        self.rerun,
+                        &self.straps[i].participant,
+                    );
+                    if let Views::Data(data) = &mut self.view {
+                        data.follow_ecg(i, paths.ecg_output(self.settings.format).to_string());
+                    }
+                    let (tx, rx) = channel(true);
+                    let (done_tx, done_rx) = oneshot::channel();
+                    let strap = &mut self.straps[i];
+                    strap.tx = tx;
+                    strap.done = Some(done_rx);
+                    let manager = Arc::clone(&strap.manager);
+                    let trial = self.meta.trial.clone();
+                    let clock = Arc::clone(&self.clock);
+                    let countdown = rx.clone();
+                    commands.push(Command::perform(
+                        tokio::spawn(async move {
+                            // waits for the last trial's event loop to finish
+                            manager
+                                .lock()
+                                .await
+                                .next_trial(rx, done_tx, trial, paths, clock)
+                                .await
+                                .map_err(|e| e.to_string())?;
+                            if !count_down(delay, countdown).await {
+                                return Ok(());
+                            }
+                            manager
+                                .lock()
+                                .await
+                                .start()
+                                .await
+                                .map_err(|e| e.to_string())
+                        }),
+                        |res| match res {
+                            Ok(Err(e)) => Message::Popup(PopupMessage::Polar(e)),
+                            Err(e) => Message::Popup(PopupMessage::Polar(e.to_string())),
+                            Ok(Ok(())) => Message::None,
+                        },
+                    ));
+                }
+                Command::batch(commands)
+            }
+            Message::BrowseDir(dir) => {
+                if let Views::Browse(browse) = &mut self.view {
+                    browse.set_dir(dir);
+                }
+                Command::none()
+            }
+            Message::FindRecordings => {
+                if let Views::Browse(browse) = &mut self.view {
+                    log::info!("user: find recordings in {}", browse.dir());
+                    browse.set_searching();
+                    let dir = std::path::PathBuf::from(browse.dir());
+                    return Command::perform(
+                        tokio::task::spawn_blocking(move || recording::find(&dir)),
+                        |res| Message::RecordingsFound(res.unwrap_or_default()),
+                    );
+                }
+                Command::none()
+            }
+            Message::RecordingsFound(recordings) => {
+                if let Views::Browse(browse) = &mut self.view {
+                    browse.set_recordings(recordings);
+                }
+                Command::none()
+            }
+            Message::OpenRecording(index) => {
+                if let Views::Browse(browse) = &mut self.view {
+                    if let Some(recording) = browse.recording(index) {
+                        log::info!("user: open recording {}", recording.title());
+                        return Command::perform(
+                            tokio::task::spawn_blocking(move || {
+                                recording::load(&recording.source)
+                                    .map(|samples| Box::new((recording, samples)))
+                            }),
+                            |res| {
+                                Message::RecordingLoaded(res.unwrap_or_else(|e| Err(e.to_string())))
+                            },
+                        );
+                    }
+                }
+                Command::none()
+            }
+            Message::RecordingLoaded(res) => {
+                if let Views::Browse(browse) = &mut self.view {
+                    match res {
+                        Ok(loaded) => {
+                            let (recording, samples) = *loaded;
+                            browse.open(Some(Review::new(recording, samples)));
+                        }
+                        Err(e) => {
+                            browse.open(None);
+                            return self.update(Message::Popup(PopupMessage::Review(e)));
+                        }
+                    }
+                }
+                Command::none()
+            }
+            Message::CloseRecording => {
+                if let Views::Browse(browse) = &mut self.view {
+                    browse.open(None);
+                }
+                Command::none()
+            }
+            Message::ReviewWindow(change) => {
+                if let Views::Browse(browse) = &mut self.view {
+                    browse.change_window(change);
                 }
                 Command::none()
             }
@@ -277,12 +993,180 @@ impl Application for App {
                 }
                 Command::none()
             }
+            Message::RawChange(raw) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    self.settings.raw = raw;
+                    menu.meta_state.meta_data.settings.raw = raw;
+                }
+                Command::none()
+            }
+            Message::SetRawPath(path) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    menu.meta_state.paths.raw = path.clone();
+                    self.paths.raw = path;
+                }
+                Command::none()
+            }
+            Message::ClearNotifications => {
+                log::info!("user: clear notifications");
+                self.notifications.clear();
+                Command::none()
+            }
+            Message::SelectProfile(name) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    let profile = menu
+                        .meta_state
+                        .profiles
+                        .iter()
+                        .find(|p| p.name == name)
+                        .cloned();
+                    if let Some(profile) = profile {
+                        log::info!("user: profile {}", name);
+                        menu.apply_profile(&profile);
+                        self.settings = profile.settings;
+                        self.paths = profile.paths;
+                    }
+                }
+                Command::none()
+            }
+            Message::SelectProtocol(name) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    log::info!("user: protocol {}", name);
+                    menu.select_protocol(&name);
+                }
+                Command::none()
+            }
+            Message::ProfileName(name) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    menu.meta_state.profile_name = name;
+                }
+                Command::none()
+            }
+            Message::ProfilePath(path) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    menu.meta_state.profile_path = path;
+                }
+                Command::none()
+            }
+            Message::SaveProfile => {
+                if let Views::Menu(menu) = &mut self.view {
+                    let events = self.notifications.events();
+                    let profile = menu.profile();
+                    if profile.name.is_empty() {
+                        events.warn("Enter a name for the profile first".to_string());
+                        return Command::none();
+                    }
+                    log::info!("user: save profile {}", profile.name);
+                    match profile.save() {
+                        Ok(()) => {
+                            events.info(format!("Profile {} saved", profile.name));
+                            menu.meta_state.profiles = Profile::list();
+                            menu.meta_state.profile = Some(profile.name);
+                        }
+                        Err(e) => events.error(format!("Profile could not be saved: {}", e)),
+                    }
+                }
+                Command::none()
+            }
+            Message::ImportProfile => {
+                if let Views::Menu(menu) = &mut self.view {
+                    let path = menu.meta_state.profile_path.clone();
+                    log::info!("user: import profile {}", path);
+                    match Profile::import(Path::new(&path)) {
+                        Ok(profile) => {
+                            self.notifications
+                                .events()
+                                .info(format!("Profile {} imported", profile.name));
+                            menu.meta_state.profiles = Profile::list();
+                            return self.update(Message::SelectProfile(profile.name));
+                        }
+                        Err(e) => self
+                            .notifications
+                            .events()
+                            .error(format!("Profile could not be imported: {}", e)),
+                    }
+                }
+                Command::none()
+            }
+            Message::ExportProfile => {
+                if let Views::Menu(menu) = &mut self.view {
+                    let events = self.notifications.events();
+                    let profile = menu.profile();
+                    let path = menu.meta_state.profile_path.clone();
+                    if profile.name.is_empty() || path.is_empty() {
+                        events.warn("Enter a profile name and a file to export to".to_string());
+                        return Command::none();
+                    }
+                    log::info!("user: export profile {} to {}", profile.name, path);
+                    match profile.write(Path::new(&path)) {
+                        Ok(()) => events.info(format!("Profile exported to {}", path)),
+                        Err(e) => events.error(format!("Profile could not be exported: {}", e)),
+                    }
+                }
+                Command::none()
+            }
+            Message::WindowResized(width, height) => {
+                self.config.window = (width, height);
+                Command::none()
+            }
+            Message::CloseRequested => {
+                log::info!("user: close");
+                self.save_config();
+                self.exit = true;
+                Command::none()
+            }
+            Message::ToggleLog => {
+                self.notifications.toggle_log();
+                Command::none()
+            }
+            Message::ExportDiagnostics => {
+                log::info!("user: export diagnostics");
+                let summary = self.diagnostics();
+                Command::perform(async move { export_bundle(&summary) }, |res| {
+                    Message::DiagnosticsExported(
+                        res.map(|p| p.display().to_string())
+                            .map_err(|e| e.to_string()),
+                    )
+                })
+            }
+            Message::ReportWritten(res) => {
+                let events = self.notifications.events();
+                match res {
+                    Ok(path) => events.info(format!("Report saved to {}", path.display())),
+                    Err(e) => events.warn(format!("Report could not be written: {}", e)),
+                }
+                Command::none()
+            }
+            Message::DiagnosticsExported(res) => {
+                let events = self.notifications.events();
+                match res {
+                    Ok(path) => events.info(format!("Diagnostics saved to {}", path)),
+                    Err(e) => events.error(format!("Diagnostics could not be saved: {}", e)),
+                }
+                Command::none()
+            }
+            Message::SetDbPath(path) => {
+                if let Views::Menu(menu) = &mut self.view {
+                    menu.meta_state.paths.db = path.clone();
+                    self.paths.db = path;
+                }
+                Command::none()
+            }
         }
     }
 
     // Tick every 16ms to update graph
     fn subscription(&self) -> Subscription<Message> {
-        iced::time::every(time::Duration::from_millis(100)).map(|_| Message::Tick)
+        Subscription::batch([
+            iced::time::every(time::Duration::from_millis(100)).map(|_| Message::Tick),
+            events_with(|event, _| match event {
+                Event::Window(window::Event::Resized { width, height }) => {
+                    Some(Message::WindowResized(width, height))
+                }
+                Event::Window(window::Event::CloseRequested) => Some(Message::CloseRequested),
+                _ => None,
+            }),
+        ])
     }
 
     fn view(&mut self) -> Element<'_, Message> {
@@ -297,7 +1181,9 @@ impl Application for App {
             Column::new()
                 .push(title)
                 .push(Rule::horizontal(10))
-                .push(body),
+                .push(body)
+                .push(Rule::horizontal(10))
+                .push(self.notifications.view()),
         );
 
         Modal::new(&mut self.modal_state, content, |state| {
@@ -315,3 +1201,23 @@ impl Application for App {
         .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stopped_count_down() {
+        let (tx, rx) = channel(true);
+        assert!(count_down(time::Duration::ZERO, rx.clone()).await);
+
+        let waiting = tokio::spawn(count_down(time::Duration::from_secs(60), rx));
+        tx.send(false).unwrap();
+        assert!(!waiting.await.unwrap());
+
+        // a trial that was replaced has no sender anymore
+        let (tx, rx) = channel(true);
+        drop(tx);
+        assert!(!count_down(time::Duration::from_secs(60), rx).await);
+    }
+}
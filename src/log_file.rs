@@ -0,0 +1,206 @@
+use chrono::Local;
+use flate2::{write::GzEncoder, Compression};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rev_lines::RevLines;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const LOG_NAME: &str = "polar-arctic.log";
+// Rotate once the current log is this big, keeping this many old logs
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+const KEEP_LOGS: usize = 5;
+
+// Per-user directory for logs and diagnostics bundles
+pub fn data_dir() -> PathBuf {
+    dirs_next::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("polar-arctic")
+}
+
+fn log_path(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join(LOG_NAME),
+        i => dir.join(format!("{}.{}", LOG_NAME, i)),
+    }
+}
+
+// Writes log records as `key=value` lines to a rotating file
+struct FileLogger {
+    dir: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl FileLogger {
+    fn write(&self, line: &str) -> io::Result<()> {
+        let mut file = self.file.lock().expect("stupid mutex");
+        let current = log_path(&self.dir, 0);
+
+        let full = fs::metadata(&current)
+            .map(|m| m.len() >= MAX_LOG_SIZE)
+            .unwrap_or(false);
+        if full {
+            *file = None;
+            for i in (0..KEEP_LOGS).rev() {
+                let from = log_path(&self.dir, i);
+                if from.exists() {
+                    fs::rename(from, log_path(&self.dir, i + 1))?;
+                }
+            }
+        }
+
+        if file.is_none() {
+            *file = Some(OpenOptions::new().append(true).create(true).open(current)?);
+        }
+        file.as_mut()
+            .expect("log file was just opened")
+            .write_all(line.as_bytes())
+    }
+}
+
+impl Log for FileLogger {
+    // our own records from info up, everything else (wgpu, btleplug...) only from warn up
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if metadata.target().starts_with("polar_arctic") {
+            metadata.level() <= Level::Info
+        } else {
+            metadata.level() <= Level::Warn
+        }
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(
+            &Local::now().to_rfc3339(),
+            record.level(),
+            record.target(),
+            &record.args().to_string(),
+        );
+        // there's nowhere left to report a broken log file
+        let _ = self.write(&line);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().expect("stupid mutex").as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+fn format_line(time: &str, level: Level, target: &str, msg: &str) -> String {
+    format!(
+        "ts={} level={} target={} msg={:?}\n",
+        time, level, target, msg
+    )
+}
+
+// Start writing the application log, logging goes nowhere if the directory can't be made
+pub fn init_logging() {
+    let dir = data_dir().join("logs");
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+    let logger = FileLogger {
+        dir,
+        file: Mutex::new(None),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+        log::info!("started version {}", env!("CARGO_PKG_VERSION"));
+    }
+}
+
+// Newest lines of the current log, newest first
+pub fn read_recent(count: usize) -> io::Result<Vec<String>> {
+    let file = File::open(log_path(&data_dir().join("logs"), 0))?;
+    let lines = RevLines::with_capacity(count * 2, BufReader::new(file))?;
+    Ok(lines.take(count).collect())
+}
+
+// Pack every log file and a summary of the app's state into a .tar.gz
+pub fn export_bundle(summary: &str) -> io::Result<PathBuf> {
+    let dir = data_dir();
+    let out = dir.join(format!(
+        "diagnostics-{}.tar.gz",
+        Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    write_bundle(&out, &dir.join("logs"), summary)?;
+    Ok(out)
+}
+
+fn write_bundle(out: &Path, logs: &Path, summary: &str) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(File::create(out)?, Compression::default()));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(summary.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Local::now().timestamp() as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, "diagnostics.txt", summary.as_bytes())?;
+
+    for i in 0..=KEEP_LOGS {
+        let path = log_path(logs, i);
+        if path.exists() {
+            let name = path.file_name().expect("log files have a name");
+            tar.append_path_with_name(&path, Path::new("logs").join(name))?;
+        }
+    }
+
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_lines() {
+        assert_eq!(
+            format_line(
+                "2022-01-01T00:00:00+00:00",
+                Level::Warn,
+                "polar_arctic",
+                "a \"b\""
+            ),
+            "ts=2022-01-01T00:00:00+00:00 level=WARN target=polar_arctic msg=\"a \\\"b\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn rotation_and_bundle() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        let logger = FileLogger {
+            dir: dir.clone(),
+            file: Mutex::new(None),
+        };
+        let line = "x".repeat(1024) + "\n";
+        for _ in 0..1100 {
+            logger.write(&line).unwrap();
+        }
+        assert!(log_path(&dir, 1).exists());
+        assert!(fs::metadata(log_path(&dir, 0)).unwrap().len() < MAX_LOG_SIZE);
+
+        let out = dir.join("bundle.tar.gz");
+        write_bundle(&out, &dir, "version 0.1.1").unwrap();
+        let decoder = flate2::read::GzDecoder::new(File::open(&out).unwrap());
+        let names: Vec<_> = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "diagnostics.txt",
+                "logs/polar-arctic.log",
+                "logs/polar-arctic.log.1"
+            ]
+        );
+    }
+}
@@ -1,6 +1,16 @@
-use iced::{Application, Settings};
-use polar_arctic::App;
+use iced::{window, Application, Settings};
+use polar_arctic::{init_logging, App, Config};
 
 fn main() -> iced::Result {
-    App::run(Settings::default())
+    init_logging();
+    let config = Config::load();
+    App::run(Settings {
+        window: window::Settings {
+            size: config.window,
+            ..window::Settings::default()
+        },
+        // settings are saved before the window closes
+        exit_on_close_request: false,
+        ..Settings::with_flags(config)
+    })
 }
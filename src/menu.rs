@@ -1,11 +1,30 @@
-use crate::{blue::setting::Setting, modal::PopupMessage, Message};
+use crate::{
+    blue::{
+        info::DeviceInfo,
+        setting::{
+            Compression, Format, PmdSettings, RecordFor, Rotation, RrLayout, Setting, Timing,
+        },
+    },
+    modal::PopupMessage,
+    profile::Profile,
+    protocol::Protocol,
+    schema::{FieldKind, Schema},
+    timer::clock,
+    Message, WhichView,
+};
 use chrono::{DateTime, Utc};
 use iced::pure::{
-    self, button, column, text_input,
+    self, button, column, row, text_input,
     widget::{PickList, Toggler},
     Pure, State,
 };
 use iced::{Column, Element, Length, Text};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+// First entry of the protocol picker
+const NO_PROTOCOL: &str = "No protocol";
 
 #[derive(Default)]
 pub struct Menu {
@@ -21,7 +40,61 @@ impl Menu {
         }
     }
 
-    pub fn view(&mut self) -> Element<Message> {
+    // Menu starting from the settings and paths used last
+    pub fn with_settings(settings: Setting, paths: Paths, schema: &Schema) -> Self {
+        let mut menu = Self::new();
+        menu.meta_state.meta_data.settings = settings;
+        menu.meta_state.meta_data.fields = schema
+            .fields
+            .iter()
+            .map(|f| (f.name.clone(), "".to_string()))
+            .collect();
+        menu.meta_state.schema = schema.clone();
+        menu.meta_state.paths = paths;
+        menu.meta_state.profiles = Profile::list();
+        menu.meta_state.protocols = Protocol::list();
+        menu
+    }
+
+    pub fn select_protocol(&mut self, name: &str) {
+        let meta = &mut self.meta_state;
+        meta.protocol = meta
+            .protocols
+            .iter()
+            .any(|p| p.name == name)
+            .then(|| name.to_string());
+    }
+
+    pub fn protocol(&self) -> Option<Protocol> {
+        let name = self.meta_state.protocol.as_ref()?;
+        self.meta_state
+            .protocols
+            .iter()
+            .find(|p| &p.name == name)
+            .cloned()
+    }
+
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        let meta = &mut self.meta_state;
+        meta.meta_data.settings = profile.settings;
+        meta.meta_data.description = profile.description.clone();
+        meta.paths = profile.paths.clone();
+        meta.profile = Some(profile.name.clone());
+        meta.profile_name = profile.name.clone();
+    }
+
+    // The menu's current settings as a profile named from the name box
+    pub fn profile(&self) -> Profile {
+        let meta = &self.meta_state;
+        Profile {
+            name: meta.profile_name.trim().to_string(),
+            description: meta.meta_data.description.clone(),
+            settings: meta.meta_data.settings,
+            paths: meta.paths.clone(),
+        }
+    }
+
+    pub fn view(&mut self) -> Element<'_, Message> {
         let title = Text::new("Metadata").size(30);
 
         Column::new()
@@ -40,6 +113,12 @@ impl Menu {
         }
     }
 
+    pub fn change_field(&mut self, index: usize, value: String) {
+        if let Some((_, v)) = self.meta_state.meta_data.fields.get_mut(index) {
+            *v = value;
+        }
+    }
+
     pub fn verify(&mut self) -> Result<(), WhichMeta> {
         let meta = &mut self.meta_state;
 
@@ -52,7 +131,7 @@ impl Menu {
         if meta.meta_data.trial.is_empty() {
             return Err(WhichMeta::Trial);
         }
-        if meta.meta_data.description.is_empty() {
+        if meta.meta_data.description.is_empty() && meta.schema.description_required {
             return Err(WhichMeta::Description);
         }
 
@@ -62,13 +141,18 @@ impl Menu {
         {
             return Err(WhichMeta::NoData);
         }
-        if meta.meta_data.settings.hr && meta.paths.hr.is_empty() {
-            return Err(WhichMeta::NoPath);
-        }
-        if meta.meta_data.settings.acc && meta.paths.acc.is_empty() {
+        let settings = &meta.meta_data.settings;
+        if settings.format == Format::Sqlite {
+            if meta.paths.db.is_empty() {
+                return Err(WhichMeta::NoPath);
+            }
+        } else if (settings.hr && meta.paths.hr.is_empty())
+            || (settings.acc && meta.paths.acc.is_empty())
+            || (settings.ecg && meta.paths.ecg.is_empty())
+        {
             return Err(WhichMeta::NoPath);
         }
-        if meta.meta_data.settings.ecg && meta.paths.ecg.is_empty() {
+        if settings.raw && meta.paths.raw.is_empty() {
             return Err(WhichMeta::NoPath);
         }
 
@@ -80,6 +164,16 @@ impl Menu {
 
         Ok(())
     }
+
+    // Check the study's own fields against the schema
+    pub fn verify_fields(&mut self) -> Result<(), String> {
+        let meta = &mut self.meta_state;
+        for (field, (_, value)) in meta.schema.fields.iter().zip(&mut meta.meta_data.fields) {
+            *value = value.trim().to_string();
+            field.check(value)?;
+        }
+        Ok(())
+    }
 }
 
 // Store meta-data about this run
@@ -91,6 +185,11 @@ pub struct Meta {
     pub description: String,
     pub date: DateTime<Utc>,
     pub settings: Setting,
+    // values of the fields from the metadata schema, by name
+    pub fields: Vec<(String, String)>,
+    // filled in once the strap is connected
+    pub device: Option<DeviceInfo>,
+    pub pmd: Option<PmdSettings>,
 }
 
 impl Default for Meta {
@@ -102,16 +201,30 @@ impl Default for Meta {
             description: "".to_string(),
             date: Utc::now(),
             settings: Setting::default(),
+            fields: vec![],
+            device: None,
+            pmd: None,
         }
     }
 }
 
-impl ToString for Meta {
-    fn to_string(&self) -> String {
-        format!(
-            "{},{},{},{},{}\n",
+impl fmt::Display for Meta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{},{},{},{},{}",
             self.id, self.session, self.trial, self.date, self.description
-        )
+        )?;
+        if !self.fields.is_empty() {
+            let fields: Vec<_> = self
+                .fields
+                .iter()
+                // these separate fields, the values themselves are stored as entered
+                .map(|(name, value)| format!("{}={}", name, value.replace([',', ';', '='], "-")))
+                .collect();
+            writeln!(f, "#meta;{}", fields.join(";"))?;
+        }
+        Ok(())
     }
 }
 
@@ -133,24 +246,167 @@ pub enum Type {
     Ecg,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Paths {
     pub hr: String,
     pub acc: String,
     pub ecg: String,
+    pub db: String,
+    pub raw: String,
+}
+
+impl Paths {
+    // Output paths for another participant recording in the same session,
+    // e.g. `output/hr.csv` becomes `output/hr-p2.csv`. The database is shared.
+    pub fn for_participant(&self, id: &str) -> Paths {
+        self.with_suffix(id)
+    }
+
+    // Output paths for a later trial, e.g. `output/hr.csv` becomes `output/hr-trial2.csv`
+    pub fn for_trial(&self, trial: &str) -> Paths {
+        self.with_suffix(&format!("trial{}", trial))
+    }
+
+    // File the ecg of a recording ends up in, the database when everything goes into one
+    pub fn ecg_output(&self, format: Format) -> &str {
+        match format {
+            Format::Sqlite => &self.db,
+            _ => &self.ecg,
+        }
+    }
+
+    fn with_suffix(&self, suffix: &str) -> Paths {
+        let rename = |path: &str| {
+            if path.is_empty() {
+                return path.to_string();
+            }
+            let path = Path::new(path);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(ext) => format!("{}-{}.{}", stem, suffix, ext.to_string_lossy()),
+                None => format!("{}-{}", stem, suffix),
+            };
+            path.with_file_name(name).to_string_lossy().into_owned()
+        };
+
+        Paths {
+            hr: rename(&self.hr),
+            acc: rename(&self.acc),
+            ecg: rename(&self.ecg),
+            db: self.db.clone(),
+            raw: rename(&self.raw),
+        }
+    }
+
+    // Fill `{id}`, `{session}` and `{trial}` in path templates from profiles
+    pub fn expand(&self, meta: &Meta) -> Paths {
+        let fill = |path: &str| {
+            path.replace("{id}", &meta.id)
+                .replace("{session}", &meta.session)
+                .replace("{trial}", &meta.trial)
+        };
+
+        Paths {
+            hr: fill(&self.hr),
+            acc: fill(&self.acc),
+            ecg: fill(&self.ecg),
+            db: fill(&self.db),
+            raw: fill(&self.raw),
+        }
+    }
+
+    // Whether every participant already gets their own files from `{id}`
+    pub fn per_participant(&self) -> bool {
+        self.all_contain("{id}")
+    }
+
+    // Whether every trial already gets its own files from `{trial}`
+    pub fn per_trial(&self) -> bool {
+        self.all_contain("{trial}")
+    }
+
+    // Folder the output is written to, above any folders from templates
+    pub fn output_dir(&self) -> String {
+        let path = [&self.hr, &self.ecg, &self.acc, &self.db, &self.raw]
+            .into_iter()
+            .find(|p| !p.is_empty());
+        let dir: std::path::PathBuf = path
+            .and_then(|p| Path::new(p).parent())
+            .map(|dir| {
+                dir.components()
+                    .take_while(|c| !c.as_os_str().to_string_lossy().contains('{'))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if dir.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            dir.to_string_lossy().into_owned()
+        }
+    }
+
+    fn all_contain(&self, key: &str) -> bool {
+        [&self.hr, &self.acc, &self.ecg, &self.raw]
+            .iter()
+            .all(|p| p.is_empty() || p.contains(key))
+    }
+}
+
+// Trial after this one, counting up the number at its end (`t09` becomes `t10`),
+// or `-2` added when it has none
+pub fn next_trial(trial: &str) -> String {
+    let stem = trial.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &trial[stem.len()..];
+    match digits.parse::<u64>().ok().and_then(|n| n.checked_add(1)) {
+        Some(n) => format!("{}{:0width$}", stem, n, width = digits.len()),
+        None => format!("{}-2", trial),
+    }
 }
 
 // Store states for meta data
 #[derive(Default, Clone)]
 pub struct MetaState {
     pub meta_data: Meta,
+    pub schema: Schema,
     pub paths: Paths,
+    pub profiles: Vec<Profile>,
+    // profile picked last
+    pub profile: Option<String>,
+    pub profile_name: String,
+    // file to import a profile from or export it to
+    pub profile_path: String,
+    pub protocols: Vec<Protocol>,
+    // protocol to run while recording, by name
+    pub protocol: Option<String>,
 }
 
 impl MetaState {
-    fn view(&mut self) -> pure::Element<Message> {
+    fn view(&mut self) -> pure::Element<'_, Message> {
         let help =
             button(Text::new("Help").size(20)).on_press(Message::Popup(PopupMessage::MenuHelp));
+        let browse = button(Text::new("Browse recordings").size(20))
+            .on_press(Message::SwitchView(WhichView::Browse));
+        // Profiles
+        let profile_title = Text::new("Profile").size(30);
+        let profile_selector = PickList::new(
+            self.profiles
+                .iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<_>>(),
+            self.profile.clone(),
+            Message::SelectProfile,
+        );
+        let profile_name = text_input("Profile name", &self.profile_name, Message::ProfileName);
+        let save_profile = button(Text::new("Save profile")).on_press(Message::SaveProfile);
+        let profile_path = text_input(
+            "Path to profile file",
+            &self.profile_path,
+            Message::ProfilePath,
+        );
+        let import = button(Text::new("Import")).on_press(Message::ImportProfile);
+        let export = button(Text::new("Export")).on_press(Message::ExportProfile);
+
         // Meta data inputs
         let id = text_input("Participant ID", &self.meta_data.id, |s| {
             Message::ChangeMeta(WhichMeta::Id, s)
@@ -161,9 +417,40 @@ impl MetaState {
         let trial = text_input("Trial number", &self.meta_data.trial, |s| {
             Message::ChangeMeta(WhichMeta::Trial, s)
         });
-        let description = text_input("Description/Notes", &self.meta_data.description, |s| {
-            Message::ChangeMeta(WhichMeta::Description, s)
-        });
+        let description = text_input(
+            if self.schema.description_required {
+                "Description/Notes"
+            } else {
+                "Description/Notes (optional)"
+            },
+            &self.meta_data.description,
+            |s| Message::ChangeMeta(WhichMeta::Description, s),
+        );
+
+        // Fields from the study's metadata schema
+        let fields = self
+            .schema
+            .fields
+            .iter()
+            .zip(&self.meta_data.fields)
+            .enumerate()
+            .fold(
+                column().spacing(20),
+                |col, (i, (field, (_, value)))| match field.kind {
+                    FieldKind::Choice => {
+                        col.push(row().spacing(20).push(Text::new(field.label())).push(
+                            PickList::new(
+                                field.values.clone(),
+                                (!value.is_empty()).then(|| value.clone()),
+                                move |v| Message::ChangeField(i, v),
+                            ),
+                        ))
+                    }
+                    _ => col.push(text_input(&field.label(), value, move |s| {
+                        Message::ChangeField(i, s)
+                    })),
+                },
+            );
 
         // Toggles for measurement types
         let hr_selector = Toggler::new(
@@ -182,6 +469,12 @@ impl MetaState {
             |b| Message::UpdateSelection(Type::Ecg, b),
         );
 
+        let raw_selector = Toggler::new(
+            self.meta_data.settings.raw,
+            Some("Log raw sensor frames".to_string()),
+            Message::RawChange,
+        );
+
         // Range and rate selector
         let select_title =
             Text::new("Select range and sample rate (only for acceleration)").size(30);
@@ -195,6 +488,100 @@ impl MetaState {
             Some(self.meta_data.settings.rate),
             Message::RateChange,
         );
+        let timing_selector = PickList::new(
+            Timing::ALL.to_vec(),
+            Some(self.meta_data.settings.timing),
+            Message::TimingChange,
+        );
+
+        // Recording length
+        let record_title = Text::new("Recording length and start delay (seconds)").size(30);
+        let record_selector = PickList::new(
+            RecordFor::ALL.to_vec(),
+            Some(self.meta_data.settings.record_for),
+            Message::RecordForChange,
+        );
+        let delay_selector = PickList::new(
+            vec![0, 3, 5, 10, 30, 60],
+            Some(self.meta_data.settings.delay),
+            Message::DelayChange,
+        );
+
+        // Protocol phases replace the recording length
+        let protocol_title = Text::new("Protocol").size(30);
+        let protocol_selector = PickList::new(
+            std::iter::once(NO_PROTOCOL.to_string())
+                .chain(self.protocols.iter().map(|p| p.name.clone()))
+                .collect::<Vec<_>>(),
+            Some(
+                self.protocol
+                    .clone()
+                    .unwrap_or_else(|| NO_PROTOCOL.to_string()),
+            ),
+            Message::SelectProtocol,
+        );
+        let phases = match self
+            .protocols
+            .iter()
+            .find(|p| Some(&p.name) == self.protocol.as_ref())
+        {
+            Some(protocol) => Text::new(
+                protocol
+                    .phases
+                    .iter()
+                    .map(|phase| format!("{} {}", phase.name, clock(phase.length())))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            None => Text::new(""),
+        };
+
+        // Connection limits
+        let connect_title = Text::new("Connection attempts and timeout (seconds)").size(30);
+        let attempts_selector = PickList::new(
+            vec![1, 3, 5, 10, 20],
+            Some(self.meta_data.settings.attempts),
+            Message::AttemptsChange,
+        );
+        let timeout_selector = PickList::new(
+            vec![15, 30, 60, 120, 300],
+            Some(self.meta_data.settings.timeout),
+            Message::TimeoutChange,
+        );
+
+        // Time shown by the live ECG graph
+        let window_title = Text::new("Live ECG graph window (seconds)").size(30);
+        let window_selector = PickList::new(
+            vec![2, 5, 10, 30, 60],
+            Some(self.meta_data.settings.chart_window),
+            Message::ChartWindowChange,
+        );
+
+        // Output format selector
+        let format_title = Text::new("Select output format").size(30);
+        let format_selector = PickList::new(
+            Format::ALL.to_vec(),
+            Some(self.meta_data.settings.format),
+            Message::FormatChange,
+        );
+
+        // Compression and rotation only apply to csv output
+        let compression_selector = PickList::new(
+            Compression::ALL.to_vec(),
+            Some(self.meta_data.settings.compression),
+            Message::CompressionChange,
+        );
+        let rotation_selector = PickList::new(
+            Rotation::ALL.to_vec(),
+            Some(self.meta_data.settings.rotation),
+            Message::RotationChange,
+        );
+
+        let rr_selector = PickList::new(
+            RrLayout::ALL.to_vec(),
+            Some(self.meta_data.settings.rr_layout),
+            Message::RrLayoutChange,
+        );
 
         // Path selectors
         let hr_path = text_input("Path to hr output file", &self.paths.hr, |s| {
@@ -209,27 +596,149 @@ impl MetaState {
             |s| Message::SetPath(Type::Ecg, s),
         );
 
+        let raw_path = text_input(
+            "Path to raw frame log",
+            &self.paths.raw,
+            Message::SetRawPath,
+        );
+
+        let db_path = text_input("Path to study database", &self.paths.db, Message::SetDbPath);
+
         let submit = button(Text::new("Submit")).on_press(Message::NewMeta);
 
+        let paths = match self.meta_data.settings.format {
+            Format::Sqlite => column().spacing(20).push(db_path),
+            Format::Parquet => column()
+                .spacing(20)
+                .push(hr_path)
+                .push(acc_path)
+                .push(ecg_path),
+            Format::Csv => column()
+                .spacing(20)
+                .push(compression_selector)
+                .push(rotation_selector)
+                .push(rr_selector)
+                .push(hr_path)
+                .push(acc_path)
+                .push(ecg_path),
+        };
+
         column()
             .spacing(20)
             .width(Length::Fill)
             .height(Length::Fill)
-            .push(help)
+            .push(row().spacing(20).push(help).push(browse))
+            .push(profile_title)
+            .push(profile_selector)
+            .push(row().spacing(20).push(profile_name).push(save_profile))
+            .push(
+                row()
+                    .spacing(20)
+                    .push(profile_path)
+                    .push(import)
+                    .push(export),
+            )
             .push(id)
             .push(session)
             .push(trial)
             .push(description)
+            .push(fields)
             .push(hr_selector)
             .push(acc_selector)
             .push(ecg_selector)
+            .push(raw_selector)
             .push(select_title)
             .push(range_selector)
             .push(rate_selector)
-            .push(hr_path)
-            .push(acc_path)
-            .push(ecg_path)
+            .push(timing_selector)
+            .push(record_title)
+            .push(record_selector)
+            .push(delay_selector)
+            .push(protocol_title)
+            .push(protocol_selector)
+            .push(phases)
+            .push(connect_title)
+            .push(attempts_selector)
+            .push(timeout_selector)
+            .push(window_title)
+            .push(window_selector)
+            .push(format_title)
+            .push(format_selector)
+            .push(paths)
+            .push(if self.meta_data.settings.raw {
+                column().push(raw_path)
+            } else {
+                column()
+            })
             .push(submit)
             .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn participant_paths() {
+        let paths = Paths {
+            hr: "output/hr.csv".to_string(),
+            ecg: "ecg".to_string(),
+            db: "output/study.db".to_string(),
+            ..Paths::default()
+        };
+        let other = paths.for_participant("p2");
+
+        assert_eq!(other.hr, "output/hr-p2.csv");
+        assert_eq!(other.ecg, "ecg-p2");
+        assert_eq!(other.acc, "");
+        assert_eq!(other.db, "output/study.db");
+
+        let template = Paths {
+            hr: "output/{id}/hr-{session}-{trial}.csv".to_string(),
+            ..Paths::default()
+        };
+        let meta = Meta {
+            id: "p2".to_string(),
+            session: "1".to_string(),
+            trial: "3".to_string(),
+            ..Meta::default()
+        };
+        assert!(template.per_participant());
+        assert!(!paths.per_participant());
+        assert_eq!(template.expand(&meta).hr, "output/p2/hr-1-3.csv");
+        assert!(template.per_trial() && !paths.per_trial());
+        assert_eq!(template.output_dir(), "output");
+        assert_eq!(Paths::default().output_dir(), ".");
+        assert_eq!(paths.for_trial("2").hr, "output/hr-trial2.csv");
+    }
+
+    #[test]
+    fn trial_numbers() {
+        assert_eq!(next_trial("1"), "2");
+        assert_eq!(next_trial("9"), "10");
+        assert_eq!(next_trial("t09"), "t10");
+        assert_eq!(next_trial("baseline"), "baseline-2");
+        let max = u64::MAX.to_string();
+        assert_eq!(next_trial(&max), format!("{}-2", max));
+    }
+
+    #[test]
+    fn meta_header() {
+        let mut meta = Meta {
+            id: "p1".to_string(),
+            ..Meta::default()
+        };
+        assert_eq!(meta.to_string().lines().count(), 1);
+
+        meta.fields = vec![
+            ("age".to_string(), "31".to_string()),
+            ("group".to_string(), "".to_string()),
+            ("site".to_string(), "a;b=c,d".to_string()),
+        ];
+        assert_eq!(
+            meta.to_string().lines().nth(1),
+            Some("#meta;age=31;group=;site=a-b-c-d")
+        );
+    }
+}
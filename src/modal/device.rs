@@ -1,3 +1,4 @@
 pub fn view() -> String {
-    "Invalid device ID. Device IDs are 6 characters long".to_string()
+    "Invalid device ID. Device IDs are the 8 characters (0-9, A-F) printed on the sensor"
+        .to_string()
 }
@@ -7,21 +7,21 @@ mod session;
 mod trial;
 
 // Decide which card to send
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum PopupMessage {
     Meta(WhichMeta),
+    #[default]
     DeviceID,
     Polar(String),
     Io(String),
-    Connected,
+    // device id of the strap
+    Connected(String),
+    InUse,
+    Field(String),
     MenuHelp,
     DataHelp,
-}
-
-impl Default for PopupMessage {
-    fn default() -> Self {
-        Self::DeviceID
-    }
+    BrowseHelp,
+    Review(String),
 }
 
 impl From<WhichMeta> for PopupMessage {
@@ -40,17 +40,24 @@ pub fn get_modal(ty: PopupMessage) -> (String, String) {
                 WhichMeta::Session => session::view(),
                 WhichMeta::Description => description::view(),
                 WhichMeta::NoData => "At least one measurement type must be specified".to_string(),
-                WhichMeta::NoPath => "A file path must be specified for each selected measurement type".to_string(),
+                WhichMeta::NoPath => "A file path must be specified for each selected measurement type, or a database path for SQLite output".to_string(),
             },
         ),
         PopupMessage::DeviceID => ("Invalid device ID".to_string(), device::view()),
         PopupMessage::Polar(err) => ("Bluetooth error".to_string(), err),
+        PopupMessage::Field(err) => ("Form not completed".to_string(), err),
         PopupMessage::Io(err) => ("Error finding output file".to_string(), err),
-        PopupMessage::Connected => (
-            "Device connected!".to_string(),
+        PopupMessage::Review(err) => ("Recording could not be opened".to_string(), err),
+        PopupMessage::Connected(id) => (
             "Device connected!".to_string(),
+            format!("Device {} connected!", id),
+        ),
+        PopupMessage::InUse => (
+            "Already recording".to_string(),
+            "This device or participant is already recording in this session".to_string(),
         ),
-        PopupMessage::MenuHelp => ("Help".to_string(), "The first four boxes are for filling in data regarding your session. Each of these boxes must be filled in. The three toggles following allow you to select which measurement types you want collected. You must select at least one data type. The last three text boxes allow you to choose where you would like your data saved. For every data type you select measurement for, you must specify a file path for it to write to. Each file path is interpreted relatively (`/` or `~` don't work). Click submit when you're done entering your data.".to_string()),
-        PopupMessage::DataHelp => ("Help".to_string(), "The `Device ID` box is where you type in your polar sensor's device ID. Press enter to start connecting to the device. A popup will appear to tell if you connection was successful or if it failed. The `Back to Menu` button will return you to the starting screen. Press `Stop Measurement` to stop collecting data from the sensor. The graph and other text display your sensor's data.".to_string()),
+        PopupMessage::MenuHelp => ("Help".to_string(), "Pick a profile to load the settings, file paths and description of a saved protocol. Type a name and press `Save profile` to save the current settings as a profile, or type the path of a profile file and press `Import` to add it to your profiles or `Export` to write the current settings to it, so other workstations can use the same settings. File paths can contain `{id}`, `{session}` and `{trial}`, which are replaced with the values you enter, and missing directories are created. The first four boxes are for filling in data regarding your session, followed by any fields your study's metadata schema adds. Each of these boxes must be filled in, unless it is marked optional. The three toggles following allow you to select which measurement types you want collected. `Log raw sensor frames` additionally saves every heart rate and ECG/acceleration notification with its receive time to a compact binary file, so recordings can be decoded again later. The frames hold the exact bytes the sensor sent. You must select at least one data type. The picker below them decides how ECG and acceleration samples are timed: spread between the timestamps the sensor gives each frame, which follows its clock, or at exactly the nominal sample rate from the start of each frame like older versions did. The recording length picker stops the measurement by itself after the chosen number of minutes, and the start delay counts down that many seconds after connecting before data is collected. The protocol picker runs a protocol from your `polar-arctic/protocols` directory instead: its phases follow each other automatically, each one is marked in the output when it starts, and the recording stops after the last one. The connection pickers limit how many times and for how many seconds the data screen tries to connect to your sensor. The output format picker chooses between CSV files and Parquet files with typed columns or a single SQLite study database; the graph window picker sets how many seconds of the live ECG graph are shown. CSV files can be compressed with gzip or zstd (`.gz`/`.zst` is added to the file name) and split into new files after a size or time limit, named like `ecg.1.csv`. The RR layout picker decides how RR intervals are stored in the heart rate file: the old layout with one extra column per interval (the default), one row per interval (timed by when the interval ended, with the heart rate only on the last row of each update), or a quoted list per row. Heart rate files start with a `#schema=...` line naming the layout. With SQLite, one database path replaces the three file paths and every participant, session and trial is stored in it. The last three text boxes allow you to choose where you would like your data saved. For every data type you select measurement for, you must specify a file path for it to write to. Each file path is interpreted relatively (`/` or `~` don't work). Click submit when you're done entering your data. Your settings and file paths are remembered for the next time you open the app.".to_string()),
+        PopupMessage::DataHelp => ("Help".to_string(), "The `Device ID` box is where you type in your polar sensor's device ID. Press enter to start connecting to the device. Several straps can record in the same session: connect them one after another, each with its own `Participant ID`. The participant from the menu writes to the menu's file paths, other participants get their ID added to the file names (`hr-p2.csv`), or share the database with SQLite, and all straps use the same session clock. Alternatively, press `Scan for devices` to list nearby Polar sensors with their ID and signal strength (RSSI), and click one to connect to it. Connecting is retried with growing pauses until it succeeds, the number of attempts from the menu is used up or the timeout passes; progress is shown next to the `Cancel connecting` button, which stops trying. If the sensor drops out during a measurement it is reconnected automatically with the same settings, and `Connection` shows whether it is connected, reconnecting or lost. Missing data is marked in the output (`#gap` lines in csv files, a `gaps` key in parquet files and the `gaps` table in the database). ECG and acceleration frames that never arrived are counted too, and `Data loss` shows how much of each stream is missing. A popup will appear to tell if you connection was successful or if it failed. The `Back to Menu` button will return you to the starting screen. Press `Stop Measurement` to stop collecting data from every sensor. Next to it a timer counts down to the start of the recording, then shows how long it has been recording and, for timed recordings, how long is left; the measurement stops by itself when the time is up. `Next trial` stops the recording, counts up the trial number and starts recording the next trial on the sensors that are already connected, with the same settings. Its files get the trial added to their names (`hr-trial2.csv`) unless the paths contain `{trial}`. When a protocol was picked, the current phase, the time left in it and its instructions for the participant are shown below. How long data was actually recorded for is saved with the recording. When a recording stops, a report with its details, heart rate and RR interval statistics, charts and events is saved next to its files as an HTML page, which can be printed to PDF from a browser. Each connected sensor gets its own graph and text showing its data. Scroll over the ECG graph to zoom in or out and drag it to look back through the last minute, which pauses it; `Pause graph` and `Resume graph` freeze the graph and make it follow the recording again, which keeps recording either way. `Save PNG` and `Save SVG` save what the graph shows as an image next to the ECG file. Next to the graph are its data, battery level, firmware version and serial number, the range and sample rates it is measuring with, which are also saved with the recording. The battery is shown in red when it is low enough that it should be charged before a long session. Connection changes, warnings and write errors from every sensor are collected in the notifications at the bottom of the window, newest first, and kept until `Clear` is pressed. Everything that happens is also written to a log file in your user data directory (`polar-arctic/logs`); `Show log` shows its newest lines and `Export diagnostics` packs the logs and a summary of your settings into a `.tar.gz` file to send along with a bug report.".to_string()),
+        PopupMessage::BrowseHelp => ("Help".to_string(), "Recordings below the output directory are listed newest first, with their participant, session, trial, streams and description. The directory starts as the folder of your output paths; type another one and press enter or `Search` to look there. Csv (also compressed), Parquet and SQLite output is found, every file of a recording is grouped together. Click a recording to open it: its duration, heart rate, heart rate variability (SDNN, RMSSD) and how many samples, markers and gaps it has are shown above charts of every recorded stream, with protocol markers as labelled lines. `Zoom in`, `Zoom out`, `Earlier` and `Later` move through the recording, `Whole recording` shows all of it again, and the page scrolls to reach every chart. `Save charts as PNG` and `Save charts as SVG` save each chart of the part that is shown as an image in the directory being browsed, for reports and papers.".to_string()),
     }
 }
@@ -0,0 +1,179 @@
+use iced::pure::{button, column, row, widget::Text, Pure, State};
+use iced::{Color, Element};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use super::{
+    blue::event::{Event, Events, Severity},
+    log_file::read_recent,
+    Message,
+};
+
+// Oldest notifications are dropped past this
+const MAX_NOTIFICATIONS: usize = 200;
+// Notifications shown at once, newest first
+const SHOWN: usize = 5;
+// Log lines shown in the log viewer
+const LOG_LINES: usize = 50;
+
+// A notification and how many times it happened in a row
+struct Entry {
+    event: Event,
+    count: u32,
+}
+
+// Every event of this run, kept until cleared
+pub struct Notifications {
+    events: Events,
+    rx: UnboundedReceiver<Event>,
+    list: Vec<Entry>,
+    // newest log lines while the log viewer is open
+    log: Option<Vec<String>>,
+    state: State,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        let (events, rx) = Events::channel("app");
+        Self {
+            events,
+            rx,
+            list: vec![],
+            log: None,
+            state: State::new(),
+        }
+    }
+}
+
+impl Notifications {
+    // Sender for the acquisition layer
+    pub fn events(&self) -> Events {
+        self.events.clone()
+    }
+
+    // Move waiting events into the list
+    pub fn poll(&mut self) {
+        while let Ok(event) = self.rx.try_recv() {
+            self.push(event);
+        }
+    }
+
+    // Repeats of the last event only bump its count so write errors can't flood the list
+    fn push(&mut self, event: Event) {
+        if let Some(last) = self.list.last_mut() {
+            if last.event.source == event.source && last.event.message == event.message {
+                last.count += 1;
+                last.event.time = event.time;
+                return;
+            }
+        }
+        self.list.push(Entry { event, count: 1 });
+        if self.list.len() > MAX_NOTIFICATIONS {
+            self.list.remove(0);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    // Open the log viewer with the newest lines, or close it
+    pub fn toggle_log(&mut self) {
+        self.log = match self.log {
+            Some(_) => None,
+            None => Some(
+                read_recent(LOG_LINES)
+                    .unwrap_or_else(|e| vec![format!("The log file could not be read: {}", e)]),
+            ),
+        };
+    }
+
+    // Every notification, oldest first, for the diagnostics bundle
+    pub fn summary(&self) -> String {
+        self.list
+            .iter()
+            .map(|entry| format!("{} (x{})\n", entry.event, entry.count))
+            .collect()
+    }
+
+    pub fn view(&mut self) -> Element<'_, Message> {
+        let errors = self
+            .list
+            .iter()
+            .filter(|e| e.event.severity == Severity::Error)
+            .count();
+        let title = Text::new(format!(
+            "Notifications ({}, {} errors)",
+            self.list.len(),
+            errors
+        ))
+        .size(20);
+        let clear = button(Text::new("Clear")).on_press(Message::ClearNotifications);
+        let toggle_log = button(Text::new(if self.log.is_some() {
+            "Hide log"
+        } else {
+            "Show log"
+        }))
+        .on_press(Message::ToggleLog);
+        let export = button(Text::new("Export diagnostics")).on_press(Message::ExportDiagnostics);
+
+        let list = self
+            .list
+            .iter()
+            .rev()
+            .take(SHOWN)
+            .fold(column().spacing(5), |col, entry| {
+                let color = match entry.event.severity {
+                    Severity::Info => Color::BLACK,
+                    Severity::Warning => Color::from_rgb(0.8, 0.5, 0.0),
+                    Severity::Error => Color::from_rgb(0.8, 0.0, 0.0),
+                };
+                let text = if entry.count > 1 {
+                    format!("{} (x{})", entry.event, entry.count)
+                } else {
+                    entry.event.to_string()
+                };
+                col.push(Text::new(text).color(color))
+            });
+
+        let log = match &self.log {
+            Some(lines) => lines.iter().fold(column().spacing(2), |col, line| {
+                col.push(Text::new(line).size(14))
+            }),
+            None => column(),
+        };
+
+        let view = column()
+            .spacing(10)
+            .push(
+                row()
+                    .spacing(20)
+                    .push(title)
+                    .push(clear)
+                    .push(toggle_log)
+                    .push(export),
+            )
+            .push(list)
+            .push(log);
+
+        Pure::new(&mut self.state, view).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_are_counted() {
+        let mut notifications = Notifications::default();
+        let events = notifications.events().with_source("7B45F72B");
+        events.error("HR writing error".to_string());
+        events.error("HR writing error".to_string());
+        events.warn("battery low".to_string());
+        notifications.poll();
+
+        assert_eq!(notifications.list.len(), 2);
+        assert_eq!(notifications.list[0].count, 2);
+        assert_eq!(notifications.list[1].event.severity, Severity::Warning);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{
+    blue::setting::Setting,
+    config::{config_dir, list_toml, to_toml},
+    menu::Paths,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A saved set of settings for a protocol that is recorded again and again
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    // filled into the description box when the profile is picked
+    pub description: String,
+    pub settings: Setting,
+    // may contain `{id}`, `{session}` and `{trial}`
+    pub paths: Paths,
+}
+
+fn profiles_dir() -> PathBuf {
+    config_dir().join("profiles")
+}
+
+// File name for a profile, anything but letters, digits, `-` and `_` becomes `_`
+fn file_name(name: &str) -> String {
+    let stem: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.toml", stem)
+}
+
+impl Profile {
+    // Every saved profile, sorted by name. Broken files are skipped.
+    pub fn list() -> Vec<Profile> {
+        list_toml(&profiles_dir(), "profile", Profile::read, |p| &p.name)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Profile> {
+        let profile: Profile = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if profile.name.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the profile has no name",
+            ));
+        }
+        Ok(profile)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, to_toml(self)?)
+    }
+
+    // Store with the other profiles, replacing one with the same name
+    pub fn save(&self) -> io::Result<()> {
+        self.save_in(&profiles_dir())
+    }
+
+    // Names like `a b` and `a_b` share a file, which must not overwrite the other profile
+    fn save_in(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(file_name(&self.name));
+        if let Ok(other) = Profile::read(&path) {
+            if other.name.trim() != self.name.trim() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("profile {} is saved under the same file name", other.name),
+                ));
+            }
+        }
+        self.write(&path)
+    }
+
+    // Copy a profile file from another workstation into the saved profiles
+    pub fn import(path: &Path) -> io::Result<Profile> {
+        let profile = Profile::read(path)?;
+        profile.save()?;
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blue::setting::Format;
+
+    #[test]
+    fn write_and_read() {
+        assert_eq!(file_name(" resting HRV/5 min"), "resting_HRV_5_min.toml");
+
+        let mut profile = Profile {
+            name: "treadmill ECG+ACC 200Hz".to_string(),
+            description: "treadmill".to_string(),
+            settings: Setting::new(false, true, true, 8, 200),
+            ..Profile::default()
+        };
+        profile.settings.format = Format::Sqlite;
+        profile.paths.db = "study/{id}.db".to_string();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.toml");
+        profile.write(&path).unwrap();
+        assert_eq!(Profile::read(&path).unwrap(), profile);
+
+        fs::write(&path, "description = \"no name\"\n").unwrap();
+        assert!(Profile::read(&path).is_err());
+
+        let saved = dir.path().join("profiles");
+        profile.save_in(&saved).unwrap();
+        profile.description = "changed".to_string();
+        profile.save_in(&saved).unwrap();
+        let other = Profile {
+            name: "treadmill ECG_ACC 200Hz".to_string(),
+            ..Profile::default()
+        };
+        assert!(other.save_in(&saved).is_err());
+        let path = saved.join(file_name(&profile.name));
+        assert_eq!(Profile::read(&path).unwrap(), profile);
+    }
+}
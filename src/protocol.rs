@@ -0,0 +1,132 @@
+use crate::config::{config_dir, list_toml};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// One step of a protocol, like a baseline or a stressor
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Phase {
+    // written to the output as the marker at the start of the phase
+    pub name: String,
+    pub minutes: u32,
+    pub seconds: u32,
+    // shown to the participant during the phase
+    pub instructions: String,
+}
+
+impl Phase {
+    pub fn length(&self) -> Duration {
+        Duration::from_secs(self.minutes as u64 * 60 + self.seconds as u64)
+    }
+}
+
+// Phases recorded one after another, read from `polar-arctic/protocols/*.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Protocol {
+    pub name: String,
+    #[serde(rename = "phase")]
+    pub phases: Vec<Phase>,
+}
+
+fn protocols_dir() -> PathBuf {
+    config_dir().join("protocols")
+}
+
+impl Protocol {
+    // Every protocol in the protocols directory, sorted by name. Broken files are skipped.
+    pub fn list() -> Vec<Protocol> {
+        list_toml(&protocols_dir(), "protocol", Protocol::read, |p| &p.name)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Protocol> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn parse(text: &str) -> Result<Protocol, String> {
+        let protocol: Protocol = toml::from_str(text).map_err(|e| e.to_string())?;
+        if protocol.name.trim().is_empty() {
+            return Err("the protocol has no name".to_string());
+        }
+        if protocol.phases.is_empty() {
+            return Err(format!("{} has no phases", protocol.name));
+        }
+        for phase in &protocol.phases {
+            if phase.name.trim().is_empty() {
+                return Err(format!("every phase of {} needs a name", protocol.name));
+            }
+            // names end up in `#marker` lines of csv files
+            if phase.name.contains([',', ';', '=', '\n']) {
+                return Err(format!(
+                    "{} may not contain `,`, `;`, `=` or line breaks",
+                    phase.name
+                ));
+            }
+            if phase.length().is_zero() {
+                return Err(format!("{} has no minutes or seconds", phase.name));
+            }
+        }
+        Ok(protocol)
+    }
+
+    pub fn length(&self) -> Duration {
+        self.phases.iter().map(Phase::length).sum()
+    }
+
+    // Phase running after recording for `elapsed` and the time left in it,
+    // None once every phase is over
+    pub fn phase_at(&self, elapsed: Duration) -> Option<(usize, Duration)> {
+        let mut end = Duration::ZERO;
+        for (i, phase) in self.phases.iter().enumerate() {
+            end += phase.length();
+            if elapsed < end {
+                return Some((i, end - elapsed));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases() {
+        let protocol = Protocol::parse(
+            r#"
+name = "stress test"
+
+[[phase]]
+name = "baseline"
+minutes = 5
+instructions = "Sit still and breathe normally."
+
+[[phase]]
+name = "stressor"
+minutes = 3
+
+[[phase]]
+name = "recovery"
+minutes = 4
+seconds = 30
+"#,
+        )
+        .unwrap();
+
+        let secs = Duration::from_secs;
+        assert_eq!(protocol.length(), secs(750));
+        assert_eq!(protocol.phase_at(Duration::ZERO), Some((0, secs(300))));
+        assert_eq!(protocol.phase_at(secs(300)), Some((1, secs(180))));
+        assert_eq!(protocol.phase_at(secs(749)), Some((2, secs(1))));
+        assert_eq!(protocol.phase_at(secs(750)), None);
+
+        assert!(Protocol::parse("name = \"empty\"\n").is_err());
+        assert!(Protocol::parse("name = \"x\"\n[[phase]]\nname = \"a\"\n").is_err());
+        assert!(Protocol::parse("name = \"x\"\n[[phase]]\nname = \"a;b\"\nseconds = 1\n").is_err());
+    }
+}
@@ -0,0 +1,673 @@
+use chrono::{DateTime, NaiveDateTime};
+use flate2::read::MultiGzDecoder;
+use parquet::{
+    file::reader::{FileReader, SerializedFileReader},
+    record::Field,
+};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+// Enough to find recordings in `{id}/{session}` folders below the output directory
+const MAX_DEPTH: usize = 3;
+
+// Where the samples of a recording are stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    // csv or parquet files, one per stream and segment
+    Files(Vec<PathBuf>),
+    // trial id in a study database
+    Database(PathBuf, i64),
+}
+
+// A past recording found in the output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub id: String,
+    pub session: String,
+    pub trial: String,
+    pub date: String,
+    pub description: String,
+    // `hr`, `ecg` and `acc`, in that order
+    pub streams: Vec<&'static str>,
+    pub source: Source,
+}
+
+impl Recording {
+    pub fn title(&self) -> String {
+        format!(
+            "{}  participant {}, session {}, trial {} ({})",
+            self.date,
+            self.id,
+            self.session,
+            self.trial,
+            self.streams.join(", ")
+        )
+    }
+}
+
+// Metadata row written at the top of every csv file
+fn parse_meta(line: &str) -> Option<[String; 5]> {
+    let mut parts = line.splitn(5, ',').map(str::to_string);
+    Some([
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+    ])
+}
+
+// Dates are written differently by csv, parquet and SQLite output
+fn normalize_date(date: &str) -> String {
+    let parsed = DateTime::parse_from_rfc3339(date)
+        .map(|d| d.naive_utc())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(date.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S%.f")
+        });
+    match parsed {
+        Ok(date) => date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => date.to_string(),
+    }
+}
+
+fn stream_of(columns: &str) -> Option<&'static str> {
+    match columns {
+        "time,bpm,rr" => Some("hr"),
+        "time,val" => Some("ecg"),
+        "time,x,y,z" => Some("acc"),
+        _ => None,
+    }
+}
+
+fn is_csv(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".csv") || name.ends_with(".csv.gz") || name.ends_with(".csv.zst")
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|ext| extensions.iter().any(|e| ext == *e))
+}
+
+// Csv files may be compressed, which their name tells
+fn open(path: &Path) -> Result<Box<dyn BufRead>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let reader: Box<dyn Read> = if has_extension(path, &["gz"]) {
+        Box::new(MultiGzDecoder::new(file))
+    } else if has_extension(path, &["zst"]) {
+        Box::new(zstd::Decoder::new(file).map_err(|e| e.to_string())?)
+    } else {
+        Box::new(file)
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+// Metadata and stream of a csv file, None if it wasn't written by polar-arctic
+fn csv_header(path: &Path) -> Option<([String; 5], &'static str)> {
+    let mut lines = open(path).ok()?.lines().map_while(Result::ok);
+    let meta = parse_meta(&lines.next()?)?;
+    // the column names follow the `#meta` and `#schema` lines
+    let stream = lines
+        .take(4)
+        .find(|line| line.starts_with("time,"))
+        .and_then(|line| stream_of(&line))?;
+    Some((meta, stream))
+}
+
+fn key_values(path: &Path) -> Result<(BTreeMap<String, String>, String), String> {
+    let reader = SerializedFileReader::new(File::open(path).map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())?;
+    let meta = reader.metadata().file_metadata();
+    let values = meta
+        .key_value_metadata()
+        .map(|kv| {
+            kv.iter()
+                .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((values, meta.schema().name().to_string()))
+}
+
+fn parquet_header(path: &Path) -> Option<([String; 5], &'static str)> {
+    let (values, name) = key_values(path).ok()?;
+    let value = |key: &str| values.get(key).cloned();
+    let stream = match name.as_str() {
+        "hr" => "hr",
+        "ecg" => "ecg",
+        "acc" => "acc",
+        _ => return None,
+    };
+    Some((
+        [
+            value("id")?,
+            value("session")?,
+            value("trial")?,
+            value("date")?,
+            value("description").unwrap_or_default(),
+        ],
+        stream,
+    ))
+}
+
+fn database_trials(path: &Path) -> Result<Vec<Recording>, rusqlite::Error> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        "SELECT t.id, s.participant, s.session, t.trial, t.date, t.description, t.hr, t.ecg, t.acc
+         FROM trials t JOIN sessions s ON s.id = t.session_id",
+    )?;
+    let trials = stmt
+        .query_map([], |row| {
+            let streams = [(6, "hr"), (7, "ecg"), (8, "acc")]
+                .into_iter()
+                .filter_map(|(i, name)| match row.get::<_, bool>(i) {
+                    Ok(true) => Some(Ok(name)),
+                    Ok(false) => None,
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Recording {
+                id: row.get(1)?,
+                session: row.get(2)?,
+                trial: row.get(3)?,
+                date: normalize_date(&row.get::<_, String>(4)?),
+                description: row.get(5)?,
+                streams,
+                source: Source::Database(path.to_path_buf(), row.get(0)?),
+            })
+        })?
+        .collect();
+    trials
+}
+
+fn walk(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("{} could not be read: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth < MAX_DEPTH {
+                walk(&path, depth + 1, files);
+            }
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+// Every recording below `dir`, newest first. Files of the same recording are grouped,
+// files that weren't written by polar-arctic are skipped.
+pub fn find(dir: &Path) -> Vec<Recording> {
+    let mut files = vec![];
+    walk(dir, 0, &mut files);
+    files.sort();
+
+    let mut grouped: BTreeMap<[String; 4], Recording> = BTreeMap::new();
+    let mut recordings = vec![];
+    for path in files {
+        let header = if is_csv(&path) {
+            csv_header(&path)
+        } else if has_extension(&path, &["parquet"]) {
+            parquet_header(&path)
+        } else {
+            if has_extension(&path, &["db", "sqlite"]) {
+                match database_trials(&path) {
+                    Ok(trials) => recordings.extend(trials),
+                    Err(e) => log::warn!("{} skipped: {}", path.display(), e),
+                }
+            }
+            continue;
+        };
+        let ([id, session, trial, date, description], stream) = match header {
+            Some(header) => header,
+            None => continue,
+        };
+        let recording = grouped
+            .entry([id.clone(), session.clone(), trial.clone(), date.clone()])
+            .or_insert_with(|| Recording {
+                id,
+                session,
+                trial,
+                date: normalize_date(&date),
+                description,
+                streams: vec![],
+                source: Source::Files(vec![]),
+            });
+        if !recording.streams.contains(&stream) {
+            recording.streams.push(stream);
+            recording
+                .streams
+                .sort_by_key(|s| ["hr", "ecg", "acc"].iter().position(|n| n == s));
+        }
+        if let Source::Files(paths) = &mut recording.source {
+            paths.push(path);
+        }
+    }
+
+    recordings.extend(grouped.into_values());
+    recordings.sort_by(|a, b| b.date.cmp(&a.date));
+    recordings
+}
+
+// Everything recorded, times in nanoseconds on the session clock
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Samples {
+    pub hr: Vec<(u64, u8)>,
+    // RR intervals in ms, at the time of the heart rate row they were written with
+    pub rr: Vec<(u64, u16)>,
+    pub ecg: Vec<(u64, i32)>,
+    pub acc: Vec<(u64, (i16, i16, i16))>,
+    pub markers: Vec<(u64, String)>,
+    pub gaps: usize,
+    // seconds, when it was stored with the recording
+    pub duration: Option<f64>,
+}
+
+impl Samples {
+    // First and last sample time of any stream
+    pub fn span(&self) -> Option<(u64, u64)> {
+        let times = [
+            self.hr.first().map(|s| s.0),
+            self.hr.last().map(|s| s.0),
+            self.ecg.first().map(|s| s.0),
+            self.ecg.last().map(|s| s.0),
+            self.acc.first().map(|s| s.0),
+            self.acc.last().map(|s| s.0),
+        ];
+        let times = times.iter().flatten();
+        Some((*times.clone().min()?, *times.max()?))
+    }
+
+    fn sort(&mut self) {
+        self.hr.sort_by_key(|s| s.0);
+        self.rr.sort_by_key(|s| s.0);
+        self.ecg.sort_by_key(|s| s.0);
+        self.acc.sort_by_key(|s| s.0);
+        // every stream's file has its own copy of the markers
+        self.markers.sort();
+        self.markers.dedup();
+    }
+}
+
+fn parse<T: std::str::FromStr>(text: Option<&str>) -> Result<T, String> {
+    let text = text.ok_or("missing column")?;
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{} is not a number", text))
+}
+
+fn read_csv(path: &Path, samples: &mut Samples) -> Result<(), String> {
+    let mut stream = None;
+    let mut layout = "columns";
+    for line in open(path)?.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if let Some(marker) = line.strip_prefix("#marker;") {
+            let mut time = None;
+            let mut label = "";
+            for part in marker.split(';') {
+                match part.split_once('=') {
+                    Some(("time", t)) => time = t.parse().ok(),
+                    Some(("label", l)) => label = l,
+                    _ => {}
+                }
+            }
+            if let Some(time) = time {
+                samples.markers.push((time, label.to_string()));
+            }
+        } else if let Some(duration) = line.strip_prefix("#duration=") {
+            samples.duration = duration.parse().ok().or(samples.duration);
+        } else if line.starts_with("#gap") {
+            samples.gaps += 1;
+        } else if let Some(schema) = line.strip_prefix("#schema=") {
+            layout = match schema.split_once(";rr=") {
+                Some((_, "rows")) => "rows",
+                Some((_, "list")) => "list",
+                _ => "columns",
+            };
+        } else if line.starts_with('#') || line.is_empty() {
+            continue;
+        } else if line.starts_with("time,") {
+            stream = stream_of(&line);
+        } else {
+            // the metadata row comes before the column names
+            let stream = match stream {
+                Some(stream) => stream,
+                None => continue,
+            };
+            let mut cols = line.split(',');
+            let time: u64 = parse(cols.next())?;
+            match stream {
+                "hr" => {
+                    // rows holding earlier rr intervals of a notification have no bpm
+                    match cols.next() {
+                        Some("") => {}
+                        bpm => samples.hr.push((time, parse(bpm)?)),
+                    }
+                    let rest: Vec<&str> = cols.collect();
+                    let rr: Vec<&str> = match layout {
+                        "list" => rest.iter().map(|r| r.trim_matches('"')).collect(),
+                        _ => rest,
+                    };
+                    for rr in rr.into_iter().filter(|r| !r.is_empty()) {
+                        samples.rr.push((time, parse(Some(rr))?));
+                    }
+                }
+                "ecg" => samples.ecg.push((time, parse(cols.next())?)),
+                _ => samples.acc.push((
+                    time,
+                    (
+                        parse(cols.next())?,
+                        parse(cols.next())?,
+                        parse(cols.next())?,
+                    ),
+                )),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn number(field: &Field) -> Result<i64, String> {
+    match *field {
+        Field::Byte(n) => Ok(n as i64),
+        Field::Short(n) => Ok(n as i64),
+        Field::Int(n) => Ok(n as i64),
+        Field::Long(n) => Ok(n),
+        Field::UByte(n) => Ok(n as i64),
+        Field::UShort(n) => Ok(n as i64),
+        Field::UInt(n) => Ok(n as i64),
+        Field::ULong(n) => Ok(n as i64),
+        _ => Err(format!("{} is not a number", field)),
+    }
+}
+
+fn read_parquet(path: &Path, samples: &mut Samples) -> Result<(), String> {
+    let (values, stream) = key_values(path)?;
+    if let Some(markers) = values.get("markers") {
+        for marker in markers.split(',') {
+            if let Some((time, label)) = marker.split_once('=') {
+                samples
+                    .markers
+                    .push((parse(Some(time))?, label.to_string()));
+            }
+        }
+    }
+    if let Some(gaps) = values.get("gaps") {
+        samples.gaps += gaps.split(',').count();
+    }
+    if let Some(duration) = values.get("duration") {
+        samples.duration = duration.parse().ok();
+    }
+
+    let reader = SerializedFileReader::new(File::open(path).map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())?;
+    for row in reader.get_row_iter(None).map_err(|e| e.to_string())? {
+        let row = row.map_err(|e| e.to_string())?;
+        let cols: Vec<&Field> = row.get_column_iter().map(|(_, field)| field).collect();
+        let col = |i: usize| cols.get(i).copied().ok_or("missing column".to_string());
+        let time = number(col(0)?)? as u64;
+        match stream.as_str() {
+            "hr" => {
+                samples.hr.push((time, number(col(1)?)? as u8));
+                if let Field::ListInternal(list) = col(2)? {
+                    for rr in list.elements() {
+                        samples.rr.push((time, number(rr)? as u16));
+                    }
+                }
+            }
+            "ecg" => samples.ecg.push((time, number(col(1)?)? as i32)),
+            _ => samples.acc.push((
+                time,
+                (
+                    number(col(1)?)? as i16,
+                    number(col(2)?)? as i16,
+                    number(col(3)?)? as i16,
+                ),
+            )),
+        }
+    }
+    Ok(())
+}
+
+fn read_database(path: &Path, trial: i64, samples: &mut Samples) -> Result<(), rusqlite::Error> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let time = |t: i64| t.max(0) as u64;
+
+    let mut stmt = conn.prepare("SELECT time, bpm FROM hr_samples WHERE trial_id = ?1")?;
+    samples.hr = stmt
+        .query_map(params![trial], |row| Ok((time(row.get(0)?), row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut stmt = conn.prepare("SELECT time, rr FROM rr_samples WHERE trial_id = ?1")?;
+    samples.rr = stmt
+        .query_map(params![trial], |row| Ok((time(row.get(0)?), row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut stmt = conn.prepare("SELECT time, val FROM ecg_samples WHERE trial_id = ?1")?;
+    samples.ecg = stmt
+        .query_map(params![trial], |row| Ok((time(row.get(0)?), row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut stmt = conn.prepare("SELECT time, x, y, z FROM acc_samples WHERE trial_id = ?1")?;
+    samples.acc = stmt
+        .query_map(params![trial], |row| {
+            Ok((time(row.get(0)?), (row.get(1)?, row.get(2)?, row.get(3)?)))
+        })?
+        .collect::<Result<_, _>>()?;
+    // databases from before markers existed don't have the table
+    if let Ok(mut stmt) = conn.prepare("SELECT time, label FROM markers WHERE trial_id = ?1") {
+        samples.markers = stmt
+            .query_map(params![trial], |row| Ok((time(row.get(0)?), row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+    }
+    samples.gaps = conn.query_row(
+        "SELECT COUNT(*) FROM gaps WHERE trial_id = ?1",
+        params![trial],
+        |row| row.get::<_, i64>(0),
+    )? as usize;
+    samples.duration = conn
+        .query_row(
+            "SELECT value FROM trial_fields WHERE trial_id = ?1 AND name = 'duration'",
+            params![trial],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|d| d.parse().ok());
+    Ok(())
+}
+
+// Read every sample of a recording
+pub fn load(source: &Source) -> Result<Samples, String> {
+    let mut samples = Samples::default();
+    match source {
+        Source::Files(paths) => {
+            for path in paths {
+                let res = if has_extension(path, &["parquet"]) {
+                    read_parquet(path, &mut samples)
+                } else {
+                    read_csv(path, &mut samples)
+                };
+                res.map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        Source::Database(path, trial) => read_database(path, *trial, &mut samples)
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+    }
+    samples.sort();
+    Ok(samples)
+}
+
+// Heart rate variability from RR intervals, in ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hrv {
+    pub count: usize,
+    pub mean: f64,
+    pub sdnn: f64,
+    pub rmssd: f64,
+}
+
+impl Hrv {
+    pub fn of(rr: &[u16]) -> Option<Self> {
+        if rr.len() < 2 {
+            return None;
+        }
+        let n = rr.len() as f64;
+        let mean = rr.iter().map(|&r| r as f64).sum::<f64>() / n;
+        let var = rr.iter().map(|&r| (r as f64 - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let diffs = rr
+            .windows(2)
+            .map(|w| (w[1] as f64 - w[0] as f64).powi(2))
+            .sum::<f64>();
+        Some(Self {
+            count: rr.len(),
+            mean,
+            sdnn: var.sqrt(),
+            rmssd: (diffs / (n - 1.0)).sqrt(),
+        })
+    }
+}
+
+// Statistics shown when reviewing a recording
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    // seconds
+    pub duration: f64,
+    // lowest, mean and highest heart rate
+    pub hr: Option<(u8, f64, u8)>,
+    pub hrv: Option<Hrv>,
+    pub ecg: usize,
+    pub acc: usize,
+    pub markers: usize,
+    pub gaps: usize,
+}
+
+impl Summary {
+    pub fn of(samples: &Samples) -> Self {
+        let duration = samples.duration.unwrap_or_else(|| {
+            samples
+                .span()
+                .map_or(0.0, |(first, last)| (last - first) as f64 / 1e9)
+        });
+        let bpm = samples.hr.iter().map(|s| s.1);
+        let hr = bpm.clone().min().zip(bpm.clone().max()).map(|(min, max)| {
+            let mean = bpm.map(|b| b as f64).sum::<f64>() / samples.hr.len() as f64;
+            (min, mean, max)
+        });
+        let rr: Vec<u16> = samples.rr.iter().map(|s| s.1).collect();
+        Self {
+            duration,
+            hr,
+            hrv: Hrv::of(&rr),
+            ecg: samples.ecg.len(),
+            acc: samples.acc.len(),
+            markers: samples.markers.len(),
+            gaps: samples.gaps,
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("Duration: {:.1} s", self.duration)];
+        if let Some((min, mean, max)) = self.hr {
+            lines.push(format!(
+                "Heart rate: {} / {:.1} / {} BPM (min / mean / max)",
+                min, mean, max
+            ));
+        }
+        if let Some(hrv) = self.hrv {
+            lines.push(format!(
+                "RR intervals: {}, mean {:.0} ms, SDNN {:.1} ms, RMSSD {:.1} ms",
+                hrv.count, hrv.mean, hrv.sdnn, hrv.rmssd
+            ));
+        }
+        if self.ecg > 0 {
+            lines.push(format!("ECG samples: {}", self.ecg));
+        }
+        if self.acc > 0 {
+            lines.push(format!("Acceleration samples: {}", self.acc));
+        }
+        lines.push(format!("Markers: {}    Gaps: {}", self.markers, self.gaps));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_and_load() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("p1")).unwrap();
+        let meta = "p1,1,2,2024-03-01 10:00:00.5 UTC,rest\n";
+        fs::write(
+            dir.join("p1/hr.csv"),
+            format!(
+                "{}#schema=2;rr=list\ntime,bpm,rr\n#device=7B45F72B\n\
+                 #marker;time=0;label=baseline\n0,60,\"1000,1010\"\n1000000000,62,\"990\"\n\
+                 #gap\n2000000000,64,\"\"\n#marker;time=2000000000;label=end\n#duration=2.500\n",
+                meta
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("p1/ecg.csv"),
+            format!(
+                "{}time,val\n#marker;time=0;label=baseline\n0,-10\n7600000,25\n",
+                meta
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("notes.csv"), "a,b\n1,2\n").unwrap();
+
+        let recordings = find(dir);
+        assert_eq!(recordings.len(), 1);
+        let recording = &recordings[0];
+        assert_eq!(recording.date, "2024-03-01 10:00:00");
+        assert_eq!(recording.streams, vec!["hr", "ecg"]);
+
+        let samples = load(&recording.source).unwrap();
+        assert_eq!(samples.hr.len(), 3);
+        assert_eq!(samples.rr, vec![(0, 1000), (0, 1010), (1_000_000_000, 990)]);
+        assert_eq!(samples.ecg, vec![(0, -10), (7_600_000, 25)]);
+        assert_eq!(
+            samples.markers,
+            vec![
+                (0, "baseline".to_string()),
+                (2_000_000_000, "end".to_string())
+            ]
+        );
+
+        let summary = Summary::of(&samples);
+        assert_eq!(summary.duration, 2.5);
+        assert_eq!(summary.hr, Some((60, 62.0, 64)));
+        assert_eq!(summary.gaps, 1);
+        let hrv = summary.hrv.unwrap();
+        assert_eq!(hrv.count, 3);
+        assert_eq!(hrv.mean, 1000.0);
+        assert_eq!(hrv.sdnn, 10.0);
+        assert!((hrv.rmssd - (250.0f64).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rr_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hr.csv");
+        fs::write(
+            &path,
+            "p1,1,1,2024-03-01 10:00:00 UTC,\n#schema=2;rr=rows\ntime,bpm,rr\n\
+             0,60,1000\n1000000000,,1010\n2000000000,62,990\n",
+        )
+        .unwrap();
+        let mut samples = Samples::default();
+        read_csv(&path, &mut samples).unwrap();
+        // the bpm of a notification counts once, however many intervals it had
+        assert_eq!(samples.hr, vec![(0, 60), (2_000_000_000, 62)]);
+        assert_eq!(samples.rr.len(), 3);
+    }
+}
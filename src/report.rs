@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{
+    blue::{info::DeviceInfo, loss::Loss},
+    export::svg,
+    menu::Meta,
+    recording::{load, Samples, Source, Summary},
+    review::plots,
+};
+
+// Seconds of ECG shown as the strip
+const ECG_STRIP: u64 = 10;
+const PLOT_SIZE: (u32, u32) = (1000, 300);
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 1000px; margin: 2em auto; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }
+svg { max-width: 100%; height: auto; }
+@media print { section { break-inside: avoid; } }";
+
+// What is known about a strap's recording besides its samples, taken when it stops
+pub struct Report {
+    // with the strap's participant as id
+    pub meta: Meta,
+    pub device_id: String,
+    pub info: Option<DeviceInfo>,
+    pub loss: Loss,
+}
+
+impl Report {
+    // Write `report-<id>-<session>-<trial>.html` into `dir` for the recording just made
+    pub fn write(&self, source: &Source, dir: &Path) -> Result<PathBuf, String> {
+        let meta = &self.meta;
+        let samples = load(source)?;
+        if samples.span().is_none() {
+            return Err(format!("nothing was recorded for {}", meta.id));
+        }
+        let path = dir.join(format!(
+            "report-{}-{}-{}.html",
+            meta.id, meta.session, meta.trial
+        ));
+        fs::write(&path, self.html(&samples)?).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    fn html(&self, samples: &Samples) -> Result<String, String> {
+        let meta = &self.meta;
+        let settings = &meta.settings;
+        let title = format!(
+            "Participant {}, session {}, trial {}",
+            meta.id, meta.session, meta.trial
+        );
+
+        let mut recording = vec![
+            ("Participant", meta.id.clone()),
+            ("Session", meta.session.clone()),
+            ("Trial", meta.trial.clone()),
+            (
+                "Date",
+                meta.date.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            ),
+            ("Description", meta.description.clone()),
+        ];
+        recording.extend(meta.fields.iter().map(|(k, v)| (k.as_str(), v.clone())));
+
+        let text = |s: &Option<String>| s.clone().unwrap_or_else(|| "?".to_string());
+        let mut device = vec![("Device ID", self.device_id.clone())];
+        if let Some(info) = &self.info {
+            device.push(("Firmware", text(&info.firmware)));
+            device.push(("Serial", text(&info.serial)));
+            device.push((
+                "Battery at connect",
+                info.battery.map_or("?".to_string(), |b| format!("{}%", b)),
+            ));
+        }
+
+        let streams: Vec<_> = [
+            (settings.hr, "heart rate"),
+            (settings.ecg, "ECG"),
+            (settings.acc, "acceleration"),
+        ]
+        .into_iter()
+        .filter(|s| s.0)
+        .map(|s| s.1)
+        .collect();
+        let mut setup = vec![("Streams", streams.join(", "))];
+        if settings.acc {
+            setup.push((
+                "Acceleration",
+                format!("±{} G at {} Hz", settings.range, settings.rate),
+            ));
+        }
+        setup.extend([
+            ("Sample timing", settings.timing.to_string()),
+            ("Output format", settings.format.to_string()),
+            ("Recording length", settings.record_for.to_string()),
+            ("Start delay", format!("{} s", settings.delay)),
+        ]);
+
+        let mut summary = Summary::of(samples).lines();
+        for (name, loss) in [("ECG", self.loss.ecg), ("Acceleration", self.loss.acc)] {
+            if loss.received > 0 {
+                summary.push(format!("{} data loss: {}", name, loss));
+            }
+        }
+
+        // heart rate over the whole recording and the first seconds of ECG
+        let (first, last) = samples.span().unwrap_or_default();
+        let mut charts = String::new();
+        if let Some((_, hr)) = plots(samples, (first, last), first)
+            .into_iter()
+            .find(|p| p.0 == "hr")
+        {
+            charts.push_str(&section("Heart rate", &svg(&hr, PLOT_SIZE)?));
+        }
+        if let Some(&(start, _)) = samples.ecg.first() {
+            let strip = (start, start + ECG_STRIP * 1_000_000_000);
+            if let Some((_, ecg)) = plots(samples, strip, first)
+                .into_iter()
+                .find(|p| p.0 == "ecg")
+            {
+                charts.push_str(&section("ECG", &svg(&ecg, PLOT_SIZE)?));
+            }
+        }
+
+        let events = if samples.markers.is_empty() {
+            "<p>No events were marked.</p>".to_string()
+        } else {
+            let rows: Vec<_> = samples
+                .markers
+                .iter()
+                .map(|(time, label)| {
+                    let secs = time.saturating_sub(first) as f64 / 1e9;
+                    (format!("{:.1} s", secs), label)
+                })
+                .collect();
+            table(&rows)
+        };
+
+        let mut page = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+             <style>\n{1}\n</style>\n</head>\n<body>\n<h1>Recording report</h1>\n<p>{0}</p>\n",
+            escape(&title),
+            STYLE
+        );
+        page.push_str(&section("Recording", &table(&recording)));
+        page.push_str(&section("Device", &table(&device)));
+        page.push_str(&section("Settings", &table(&setup)));
+        page.push_str(&section("Summary", &list(&summary)));
+        page.push_str(&charts);
+        page.push_str(&section("Events", &events));
+        page.push_str("</body>\n</html>\n");
+        Ok(page)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn section(title: &str, body: &str) -> String {
+    format!(
+        "<section>\n<h2>{}</h2>\n{}\n</section>\n",
+        escape(title),
+        body
+    )
+}
+
+fn table<K: AsRef<str>, V: AsRef<str>>(rows: &[(K, V)]) -> String {
+    let rows: String = rows
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                escape(name.as_ref()),
+                escape(value.as_ref())
+            )
+        })
+        .collect();
+    format!("<table>\n{}</table>", rows)
+}
+
+fn list(items: &[String]) -> String {
+    let items: String = items
+        .iter()
+        .map(|item| format!("<li>{}</li>\n", escape(item)))
+        .collect();
+    format!("<ul>\n{}</ul>", items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_page() {
+        let secs = |s: u64| s * 1_000_000_000;
+        let samples = Samples {
+            hr: vec![(secs(1), 60), (secs(2), 64), (secs(3), 62)],
+            rr: vec![(secs(1), 1000), (secs(2), 940), (secs(3), 960)],
+            ecg: (0..1300).map(|i| (secs(1) + i * 7_692_307, 100)).collect(),
+            markers: vec![(secs(2), "stressor".to_string())],
+            ..Samples::default()
+        };
+        let mut report = Report {
+            meta: Meta {
+                id: "p<1>".to_string(),
+                session: "1".to_string(),
+                trial: "2".to_string(),
+                fields: vec![("group".to_string(), "control".to_string())],
+                ..Meta::default()
+            },
+            device_id: "ABCD1234".to_string(),
+            info: None,
+            loss: Loss::default(),
+        };
+        report.loss.ecg.received = 1300;
+        let page = report.html(&samples).unwrap();
+
+        assert!(page.contains("Participant p&lt;1&gt;, session 1, trial 2"));
+        assert!(page.contains("<th>group</th><td>control</td>"));
+        assert!(page.contains("Heart rate: 60 / 62.0 / 64 BPM"));
+        assert!(page.contains("ECG data loss: 0.00%"));
+        assert!(page.contains("<th>1.0 s</th><td>stressor</td>"));
+        assert_eq!(page.matches("<svg").count(), 2);
+
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("hr.csv");
+        fs::write(&empty, "p1,1,2,2024-03-01 10:00:00 UTC,\ntime,bpm,rr\n").unwrap();
+        let source = Source::Files(vec![empty]);
+        assert!(report.write(&source, dir.path()).is_err());
+    }
+}
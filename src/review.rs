@@ -0,0 +1,522 @@
+use iced::pure::{button, column, row, text_input, widget::Text, Pure, State};
+use iced::{scrollable, Column, Length, Rule, Scrollable};
+use plotters::prelude::*;
+use plotters_iced::{Chart, ChartWidget, DrawingBackend};
+
+use std::path::{Path, PathBuf};
+
+use super::{
+    export::{image_path, save, ImageFormat},
+    modal::PopupMessage,
+    recording::{Recording, Samples, Summary},
+    Message, WhichView,
+};
+
+// Points drawn per line, more only slow drawing down
+const MAX_POINTS: usize = 2000;
+// Shortest part of a recording that can be zoomed to
+const MIN_WINDOW: u64 = 1_000_000_000;
+
+// How to move the shown part of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowChange {
+    ZoomIn,
+    ZoomOut,
+    Earlier,
+    Later,
+    All,
+}
+
+// A recording opened for review
+pub struct Review {
+    recording: Recording,
+    samples: Samples,
+    summary: Summary,
+    // first and last sample time
+    span: (u64, u64),
+    // part of the recording that is shown, on the session clock
+    window: (u64, u64),
+}
+
+impl Review {
+    pub fn new(recording: Recording, samples: Samples) -> Self {
+        let span = samples.span().unwrap_or_default();
+        Self {
+            recording,
+            summary: Summary::of(&samples),
+            samples,
+            span,
+            window: span,
+        }
+    }
+
+    pub fn change(&mut self, change: WindowChange) {
+        let (first, last) = self.span;
+        let (start, end) = self.window;
+        let length = end - start;
+        let (start, length) = match change {
+            WindowChange::ZoomIn => {
+                let zoomed = (length / 2).max(MIN_WINDOW);
+                (start + (length.saturating_sub(zoomed)) / 2, zoomed)
+            }
+            WindowChange::ZoomOut => {
+                let zoomed = length.saturating_mul(2);
+                (start.saturating_sub(length / 2), zoomed)
+            }
+            WindowChange::Earlier => (start.saturating_sub(length / 2), length),
+            WindowChange::Later => (start + length / 2, length),
+            WindowChange::All => (first, last - first),
+        };
+        // keep the window inside the recording
+        let length = length.min(last - first);
+        let start = start.clamp(first, last - length);
+        self.window = (start, start + length);
+    }
+
+    fn seconds(&self, time: u64) -> f64 {
+        time.saturating_sub(self.span.0) as f64 / 1e9
+    }
+
+    fn view(&self) -> Column<'_, Message> {
+        let title = iced::Text::new(self.recording.title()).size(25);
+        let description = iced::Text::new(&self.recording.description);
+        let summary = self
+            .summary
+            .lines()
+            .into_iter()
+            .fold(Column::new().spacing(5), |col, line| {
+                col.push(iced::Text::new(line))
+            });
+        let markers: Vec<String> = self
+            .samples
+            .markers
+            .iter()
+            .map(|(time, label)| format!("{:.1} s {}", self.seconds(*time), label))
+            .collect();
+        let markers = iced::Text::new(if markers.is_empty() {
+            "Markers: -".to_string()
+        } else {
+            format!("Markers: {}", markers.join(", "))
+        });
+        let shown = iced::Text::new(format!(
+            "Showing {:.1} s to {:.1} s of {:.1} s",
+            self.seconds(self.window.0),
+            self.seconds(self.window.1),
+            self.seconds(self.span.1)
+        ));
+
+        self.plots().into_iter().fold(
+            Column::new()
+                .spacing(20)
+                .push(title)
+                .push(description)
+                .push(summary)
+                .push(markers)
+                .push(shown),
+            |col, (_, plot)| {
+                col.push(
+                    ChartWidget::new(plot)
+                        .width(Length::Fill)
+                        .height(Length::Units(300)),
+                )
+            },
+        )
+    }
+
+    fn plots(&self) -> Vec<(&'static str, Plot<'_>)> {
+        plots(&self.samples, self.window, self.span.0)
+    }
+
+    // Save every chart of the shown window to `dir`, named after the recording,
+    // stream and seconds shown
+    fn export(&self, dir: &Path, format: ImageFormat) -> Result<Vec<PathBuf>, String> {
+        let recording = &self.recording;
+        self.plots()
+            .into_iter()
+            .map(|(stream, plot)| {
+                let name = format!(
+                    "{}-{}-{}-{}-{:.0}s-{:.0}s",
+                    recording.id,
+                    recording.session,
+                    recording.trial,
+                    stream,
+                    self.seconds(self.window.0),
+                    self.seconds(self.window.1)
+                );
+                let path = image_path(dir, &name, format);
+                save(&plot, &path, format)?;
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+// Charts of every recorded stream in `window`, with the stream's name. `first` is shown as 0 s.
+pub fn plots(samples: &Samples, window: (u64, u64), first: u64) -> Vec<(&'static str, Plot<'_>)> {
+    let plot = |title, unit| Plot {
+        title,
+        unit,
+        lines: vec![],
+        markers: &samples.markers,
+        window,
+        first,
+    };
+    let mut plots = vec![];
+    if !samples.hr.is_empty() {
+        let mut hr = plot("Heart rate", "BPM");
+        hr.lines.push((hr.points(&samples.hr, |b| *b as f64), RED));
+        plots.push(("hr", hr));
+    }
+    if !samples.rr.is_empty() {
+        let mut rr = plot("RR intervals", "ms");
+        rr.lines
+            .push((rr.points(&samples.rr, |r| *r as f64), BLACK));
+        plots.push(("rr", rr));
+    }
+    if !samples.ecg.is_empty() {
+        let mut ecg = plot("ECG", "µV");
+        ecg.lines
+            .push((ecg.points(&samples.ecg, |v| *v as f64), BLACK));
+        plots.push(("ecg", ecg));
+    }
+    if !samples.acc.is_empty() {
+        let mut acc = plot("Acceleration", "mG");
+        for (axis, color) in [(0, RED), (1, GREEN), (2, BLUE)] {
+            let line = acc.points(&samples.acc, |a| match axis {
+                0 => a.0 as f64,
+                1 => a.1 as f64,
+                _ => a.2 as f64,
+            });
+            acc.lines.push((line, color));
+        }
+        plots.push(("acc", acc));
+    }
+    plots
+}
+
+// One chart of a recording, limited to the shown window
+pub struct Plot<'a> {
+    title: &'static str,
+    unit: &'static str,
+    lines: Vec<(Vec<(f64, f64)>, RGBColor)>,
+    markers: &'a [(u64, String)],
+    window: (u64, u64),
+    // time shown as 0 s
+    first: u64,
+}
+
+impl Plot<'_> {
+    fn seconds(&self, time: u64) -> f64 {
+        time.saturating_sub(self.first) as f64 / 1e9
+    }
+
+    // Samples inside the window, thinned to the lowest and highest value of each
+    // bucket so peaks stay visible
+    fn points<T>(&self, samples: &[(u64, T)], value: impl Fn(&T) -> f64) -> Vec<(f64, f64)> {
+        let start = samples.partition_point(|s| s.0 < self.window.0);
+        let end = samples.partition_point(|s| s.0 <= self.window.1);
+        let shown = &samples[start..end];
+        let bucket = (shown.len() * 2 / MAX_POINTS).max(1);
+        if bucket == 1 {
+            return shown
+                .iter()
+                .map(|(t, v)| (self.seconds(*t), value(v)))
+                .collect();
+        }
+        shown
+            .chunks(bucket)
+            .flat_map(|chunk| {
+                let (mut low, mut high) = (&chunk[0], &chunk[0]);
+                for s in chunk {
+                    if value(&s.1) < value(&low.1) {
+                        low = s;
+                    }
+                    if value(&s.1) > value(&high.1) {
+                        high = s;
+                    }
+                }
+                let (a, b) = if low.0 <= high.0 {
+                    (low, high)
+                } else {
+                    (high, low)
+                };
+                [
+                    (self.seconds(a.0), value(&a.1)),
+                    (self.seconds(b.0), value(&b.1)),
+                ]
+            })
+            .collect()
+    }
+}
+
+impl Chart<Message> for Plot<'_> {
+    fn build_chart<DB: DrawingBackend>(&self, mut builder: ChartBuilder<DB>) {
+        let values = self
+            .lines
+            .iter()
+            .flat_map(|(line, _)| line.iter().map(|p| p.1));
+        let low = values.clone().fold(f64::INFINITY, f64::min);
+        let high = values.fold(f64::NEG_INFINITY, f64::max);
+        let (low, high) = if low.is_finite() {
+            let pad = ((high - low) * 0.05).max(1.0);
+            (low - pad, high + pad)
+        } else {
+            (0.0, 1.0)
+        };
+        let (start, end) = (self.seconds(self.window.0), self.seconds(self.window.1));
+        let end = if end > start { end } else { start + 1.0 };
+
+        let mut ctx = builder
+            .set_label_area_size(LabelAreaPosition::Bottom, 40)
+            .set_label_area_size(LabelAreaPosition::Left, 60)
+            .caption(self.title, ("sans-serif", 20u32))
+            .build_cartesian_2d(start..end, low..high)
+            .expect("Error making graph");
+        ctx.configure_mesh()
+            .x_desc("Time (s)")
+            .y_desc(self.unit)
+            .draw()
+            .expect("Error making graph");
+
+        for (line, color) in &self.lines {
+            ctx.draw_series(LineSeries::new(line.iter().copied(), color))
+                .expect("Error making graph");
+        }
+
+        // event markers as labelled vertical lines
+        let shown = self
+            .markers
+            .iter()
+            .filter(|(time, _)| (self.window.0..=self.window.1).contains(time));
+        for (time, label) in shown {
+            let x = self.seconds(*time);
+            ctx.draw_series(std::iter::once(PathElement::new(
+                vec![(x, low), (x, high)],
+                MAGENTA,
+            )))
+            .expect("Error making graph");
+            ctx.draw_series(std::iter::once(plotters::element::Text::new(
+                label.clone(),
+                (x, high),
+                ("sans-serif", 15u32).into_font().color(&MAGENTA),
+            )))
+            .expect("Error making graph");
+        }
+    }
+}
+
+// Lists past recordings and shows one of them
+pub struct Browse {
+    dir: String,
+    recordings: Vec<Recording>,
+    searching: bool,
+    loading: bool,
+    review: Option<Review>,
+    state: State,
+    scroll: scrollable::State,
+}
+
+impl Browse {
+    pub fn new(dir: String) -> Self {
+        Self {
+            dir,
+            recordings: vec![],
+            searching: false,
+            loading: false,
+            review: None,
+            state: State::new(),
+            scroll: scrollable::State::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    pub fn set_dir(&mut self, dir: String) {
+        self.dir = dir;
+    }
+
+    pub fn set_searching(&mut self) {
+        self.searching = true;
+    }
+
+    pub fn set_recordings(&mut self, recordings: Vec<Recording>) {
+        self.searching = false;
+        self.recordings = recordings;
+    }
+
+    pub fn recording(&mut self, index: usize) -> Option<Recording> {
+        let recording = self.recordings.get(index).cloned();
+        self.loading = recording.is_some();
+        recording
+    }
+
+    // Show a recording, or None to go back to the list
+    pub fn open(&mut self, review: Option<Review>) {
+        self.loading = false;
+        self.review = review;
+    }
+
+    pub fn change_window(&mut self, change: WindowChange) {
+        if let Some(review) = &mut self.review {
+            review.change(change);
+        }
+    }
+
+    // Save the charts of the open recording in the directory being browsed
+    pub fn export(&self, format: ImageFormat) -> Result<Vec<PathBuf>, String> {
+        match &self.review {
+            Some(review) => review.export(Path::new(&self.dir), format),
+            None => Err("no recording is open".to_string()),
+        }
+    }
+
+    pub fn view(&mut self) -> iced::Element<'_, Message> {
+        let Self {
+            dir,
+            recordings,
+            searching,
+            loading,
+            review,
+            state,
+            scroll,
+        } = self;
+
+        let help = button(Text::new("Help").size(20))
+            .on_press(Message::Popup(PopupMessage::BrowseHelp))
+            .padding(15);
+
+        let controls = match review {
+            Some(_) => {
+                let change = |label, change| {
+                    button(Text::new(label)).on_press(Message::ReviewWindow(change))
+                };
+                column()
+                    .spacing(20)
+                    .push(
+                        row()
+                            .spacing(20)
+                            .push(
+                                button(Text::new("Back to recordings").size(20))
+                                    .on_press(Message::CloseRecording)
+                                    .padding(15),
+                            )
+                            .push(help)
+                            .push(change("Zoom in", WindowChange::ZoomIn))
+                            .push(change("Zoom out", WindowChange::ZoomOut))
+                            .push(change("Earlier", WindowChange::Earlier))
+                            .push(change("Later", WindowChange::Later))
+                            .push(change("Whole recording", WindowChange::All)),
+                    )
+                    .push(
+                        ImageFormat::ALL
+                            .into_iter()
+                            .fold(row().spacing(20), |row, format| {
+                                row.push(
+                                    button(Text::new(format!("Save charts as {}", format)))
+                                        .on_press(Message::ExportReview(format)),
+                                )
+                            }),
+                    )
+            }
+            None => {
+                let header = row()
+                    .push(
+                        button(Text::new("Back to menu").size(20))
+                            .on_press(Message::SwitchView(WhichView::Menu))
+                            .padding(15),
+                    )
+                    .push(help);
+                let input = text_input("Output directory", dir, Message::BrowseDir)
+                    .padding(15)
+                    .size(20)
+                    .on_submit(Message::FindRecordings);
+                let search = if *searching {
+                    button(Text::new("Searching..."))
+                } else {
+                    button(Text::new("Search")).on_press(Message::FindRecordings)
+                };
+                let list = if *loading {
+                    column().push(Text::new("Opening recording..."))
+                } else if recordings.is_empty() && !*searching {
+                    column().push(Text::new(format!("No recordings found in {}", dir)))
+                } else {
+                    recordings.iter().enumerate().fold(
+                        column().spacing(5),
+                        |col, (i, recording)| {
+                            col.push(
+                                button(Text::new(format!(
+                                    "{}  {}",
+                                    recording.title(),
+                                    recording.description
+                                )))
+                                .on_press(Message::OpenRecording(i)),
+                            )
+                        },
+                    )
+                };
+                column()
+                    .spacing(20)
+                    .push(header)
+                    .push(Text::new("Recordings").size(30))
+                    .push(row().spacing(20).push(input).push(search))
+                    .push(list)
+            }
+        };
+
+        let content = Column::new()
+            .spacing(20)
+            .push(Pure::new(state, controls))
+            .push(Rule::horizontal(10));
+        let content = match review {
+            Some(review) => content.push(review.view()),
+            None => content,
+        };
+        Scrollable::new(scroll)
+            .height(Length::Fill)
+            .push(content)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::Source;
+
+    #[test]
+    fn window() {
+        let secs = |s: u64| s * 1_000_000_000;
+        let samples = Samples {
+            hr: vec![(secs(10), 60), (secs(110), 70)],
+            ..Samples::default()
+        };
+        let recording = Recording {
+            id: "p1".to_string(),
+            session: "1".to_string(),
+            trial: "1".to_string(),
+            date: "2024-03-01 10:00:00".to_string(),
+            description: "".to_string(),
+            streams: vec!["hr"],
+            source: Source::Files(vec![]),
+        };
+        let mut review = Review::new(recording, samples);
+        assert_eq!(review.window, (secs(10), secs(110)));
+
+        review.change(WindowChange::ZoomIn);
+        assert_eq!(review.window, (secs(35), secs(85)));
+        review.change(WindowChange::Later);
+        review.change(WindowChange::Later);
+        assert_eq!(review.window, (secs(60), secs(110)));
+        review.change(WindowChange::ZoomOut);
+        assert_eq!(review.window, (secs(10), secs(110)));
+
+        for _ in 0..10 {
+            review.change(WindowChange::ZoomIn);
+        }
+        assert_eq!(review.window.1 - review.window.0, MIN_WINDOW);
+        review.change(WindowChange::All);
+        assert_eq!(review.window, (secs(10), secs(110)));
+    }
+}
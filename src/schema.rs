@@ -0,0 +1,195 @@
+use crate::config::config_dir;
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const SCHEMA_NAME: &str = "metadata.toml";
+
+// What kind of value a metadata field holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    #[default]
+    Text,
+    Number,
+    // one of `values`
+    Choice,
+}
+
+// A study specific metadata field shown on the menu under the built in ones
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Field {
+    // key used in the output files
+    pub name: String,
+    // shown on the menu, the name if empty
+    pub label: String,
+    pub kind: FieldKind,
+    pub required: bool,
+    pub values: Vec<String>,
+    // the whole value has to match this
+    pub pattern: Option<String>,
+}
+
+impl Field {
+    fn title(&self) -> &str {
+        if self.label.is_empty() {
+            &self.name
+        } else {
+            &self.label
+        }
+    }
+
+    pub fn label(&self) -> String {
+        if self.required {
+            self.title().to_string()
+        } else {
+            format!("{} (optional)", self.title())
+        }
+    }
+
+    // Reason the value can't be used, if any
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        let label = self.title();
+        if value.is_empty() {
+            return if self.required {
+                Err(format!("{} must be filled in", label))
+            } else {
+                Ok(())
+            };
+        }
+        match self.kind {
+            FieldKind::Number if value.parse::<f64>().is_err() => {
+                return Err(format!("{} must be a number", label));
+            }
+            FieldKind::Choice if !self.values.iter().any(|v| v == value) => {
+                return Err(format!(
+                    "{} must be one of: {}",
+                    label,
+                    self.values.join(", ")
+                ));
+            }
+            _ => {}
+        }
+        if let Some(pattern) = &self.pattern {
+            let re = anchored(pattern).map_err(|e| e.to_string())?;
+            if !re.is_match(value) {
+                return Err(format!("{} must match {}", label, pattern));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+// Metadata fields a study asks for, read from `metadata.toml` in the config directory
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Schema {
+    pub description_required: bool,
+    #[serde(rename = "field")]
+    pub fields: Vec<Field>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self {
+            description_required: true,
+            fields: vec![],
+        }
+    }
+}
+
+pub fn schema_path() -> PathBuf {
+    config_dir().join(SCHEMA_NAME)
+}
+
+impl Schema {
+    // No schema file means only the built in fields
+    pub fn load() -> Result<Self, String> {
+        match fs::read_to_string(schema_path()) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let schema: Schema = toml::from_str(text).map_err(|e| e.to_string())?;
+        for field in &schema.fields {
+            if field.name.is_empty() {
+                return Err("every field needs a name".to_string());
+            }
+            // names end up in csv headers and database rows
+            if !field
+                .name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(format!(
+                    "{} may only contain letters, digits, `-` and `_`",
+                    field.name
+                ));
+            }
+            if field.kind == FieldKind::Choice && field.values.is_empty() {
+                return Err(format!("{} has no values to choose from", field.name));
+            }
+            if let Some(pattern) = &field.pattern {
+                anchored(pattern).map_err(|e| format!("{}: {}", field.name, e))?;
+            }
+        }
+        Ok(schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let schema = Schema::parse(
+            r#"
+description_required = false
+
+[[field]]
+name = "age"
+kind = "number"
+required = true
+
+[[field]]
+name = "group"
+label = "Group"
+kind = "choice"
+values = ["control", "treatment"]
+
+[[field]]
+name = "room"
+pattern = "[A-C][0-9]{2}"
+"#,
+        )
+        .unwrap();
+
+        assert!(!schema.description_required);
+        let [age, group, room] = &schema.fields[..] else {
+            panic!("three fields expected");
+        };
+        assert_eq!(group.label(), "Group (optional)");
+        assert_eq!(age.check(""), Err("age must be filled in".to_string()));
+        assert_eq!(age.check("x"), Err("age must be a number".to_string()));
+        assert!(age.check("31").is_ok());
+        assert!(group.check("").is_ok());
+        assert!(group.check("placebo").is_err());
+        assert!(room.check("B12").is_ok());
+        assert!(room.check("B123").is_err());
+
+        assert!(Schema::parse("[[field]]\nname = \"x\"\nkind = \"choice\"\n").is_err());
+        assert!(Schema::parse("[[field]]\nname = \"a;b\"\n").is_err());
+        assert!(Schema::parse("[[field]]\nname = \"x\"\npattern = \"(\"\n").is_err());
+    }
+}
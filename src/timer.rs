@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+// Counts down to the start of a recording, then up until it is over
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    // when measuring starts, after the countdown
+    start: Instant,
+    length: Option<Duration>,
+    // when the recording was stopped, by the timer or by hand
+    stopped: Option<Instant>,
+}
+
+impl Timer {
+    pub fn new(now: Instant, delay: Duration, length: Option<Duration>) -> Self {
+        Self {
+            start: now + delay,
+            length,
+            stopped: None,
+        }
+    }
+
+    // Time left before measuring starts
+    pub fn countdown(&self, now: Instant) -> Duration {
+        self.start.saturating_duration_since(now)
+    }
+
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        self.stopped
+            .unwrap_or(now)
+            .saturating_duration_since(self.start)
+    }
+
+    // Time recorded so far, None during the countdown and after stopping
+    pub fn running(&self, now: Instant) -> Option<Duration> {
+        (self.countdown(now).is_zero() && self.stopped.is_none()).then(|| self.elapsed(now))
+    }
+
+    // True once, when the recording has run for its full length
+    pub fn finish(&mut self, now: Instant) -> bool {
+        match self.length {
+            Some(length) if self.stopped.is_none() && self.elapsed(now) >= length => {
+                self.stopped = Some(self.start + length);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        if self.stopped.is_none() {
+            self.stopped = Some(now.max(self.start));
+        }
+    }
+
+    // Shown on the data screen
+    pub fn text(&self, now: Instant) -> String {
+        let countdown = self.countdown(now);
+        if !countdown.is_zero() && self.stopped.is_none() {
+            return format!("Starting in {} s", countdown.as_secs_f64().ceil());
+        }
+        let elapsed = self.elapsed(now);
+        match (self.stopped, self.length) {
+            (Some(_), _) => format!("Recorded {}", clock(elapsed)),
+            (None, Some(length)) => format!(
+                "Recording {} ({} left)",
+                clock(elapsed),
+                clock(length.saturating_sub(elapsed))
+            ),
+            (None, None) => format!("Recording {}", clock(elapsed)),
+        }
+    }
+}
+
+// mm:ss, or h:mm:ss for long recordings
+pub fn clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countdown_and_stop() {
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        let mut timer = Timer::new(now, secs(3), Some(secs(60)));
+
+        assert_eq!(timer.text(now), "Starting in 3 s");
+        assert_eq!(timer.running(now), None);
+        assert_eq!(timer.running(now + secs(5)), Some(secs(2)));
+        assert_eq!(timer.text(now + secs(13)), "Recording 00:10 (00:50 left)");
+        assert!(!timer.finish(now + secs(62)));
+        assert!(timer.finish(now + secs(63)));
+        assert!(!timer.finish(now + secs(64)));
+        assert_eq!(timer.text(now + secs(100)), "Recorded 01:00");
+
+        let mut open = Timer::new(now, Duration::ZERO, None);
+        assert!(!open.finish(now + secs(7200)));
+        open.stop(now + secs(3725));
+        assert_eq!(open.text(now + secs(7200)), "Recorded 1:02:05");
+    }
+}